@@ -0,0 +1,93 @@
+// Chunk meshes: the standard pbr shader with the base color taken from the tile of the block texture atlas
+#import bevy_pbr::{
+    mesh_functions,
+    view_transformations::position_world_to_clip,
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+}
+#import bevy_render::instance_index::get_instance_index
+
+@group(1) @binding(100) var<uniform> atlas_columns: u32;
+@group(1) @binding(101) var atlas_texture: texture_2d<f32>;
+@group(1) @binding(102) var atlas_sampler: sampler;
+
+// Same as NO_TEXTURE in atlas.rs
+const NO_TEXTURE: u32 = 0xffffffffu;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(5) color: vec4<f32>,
+    @location(8) tile: u32,
+};
+
+// The standard vertex output with the tile added
+struct VoxelVertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(4) color: vec4<f32>,
+    @location(5) @interpolate(flat) instance_index: u32,
+    @location(6) @interpolate(flat) tile: u32,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VoxelVertexOutput {
+    var out: VoxelVertexOutput;
+
+    let model = mesh_functions::get_model_matrix(vertex.instance_index);
+
+    out.world_position = mesh_functions::mesh_position_local_to_world(model, vec4<f32>(vertex.position, 1.0));
+    out.position = position_world_to_clip(out.world_position.xyz);
+    out.world_normal = mesh_functions::mesh_normal_local_to_world(vertex.normal, get_instance_index(vertex.instance_index));
+    out.uv = vertex.uv;
+    out.color = vertex.color;
+    out.instance_index = get_instance_index(vertex.instance_index);
+    out.tile = vertex.tile;
+
+#ifdef BASE_INSTANCE_WORKAROUND
+    // Same as in the standard mesh shader, keeps the push constant used
+    out.position.x += min(f32(get_instance_index(0u)), 0.0);
+#endif
+
+    return out;
+}
+
+@fragment
+fn fragment(in: VoxelVertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    var mesh: VertexOutput;
+    mesh.position = in.position;
+    mesh.world_position = in.world_position;
+    mesh.world_normal = in.world_normal;
+    mesh.uv = in.uv;
+    mesh.color = in.color;
+    mesh.instance_index = in.instance_index;
+
+    var pbr_input = pbr_input_from_standard_material(mesh, is_front);
+
+    // Use the slope of the uvs before fract, otherwise the jump at every block border picks the smallest mip level there
+    // Taken outside of the branch, since slopes need the neighbor pixels to run the same code
+    let tile_size = 1.0 / f32(atlas_columns);
+    let uv_dx = dpdx(in.uv) * tile_size;
+    let uv_dy = dpdy(in.uv) * tile_size;
+
+    if in.tile != NO_TEXTURE {
+        // The uvs go from 0 to the size of the merged face in blocks, so the tile repeats once per block
+        let tile = vec2<f32>(f32(in.tile % atlas_columns), f32(in.tile / atlas_columns));
+        let atlas_uv = (tile + fract(in.uv)) * tile_size;
+
+        pbr_input.material.base_color *= textureSampleGrad(atlas_texture, atlas_sampler, atlas_uv, uv_dx, uv_dy);
+    }
+
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+
+    return out;
+}
@@ -0,0 +1,57 @@
+use bevy::prelude::*;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use my_bevy_game::world::{BlockId, NoiseTerrain, TerrainGenerator, WorldGenSettings, SECTION_HEIGHT};
+
+// Generate a chunk the old way, where every block samples the height noise and the biomes of its column again
+fn generate_per_block(terrain: &NoiseTerrain, position: IVec3, size: IVec3) -> Vec<BlockId> {
+    let mut blocks: Vec<BlockId> = Vec::with_capacity((size.x * size.y * size.z) as usize);
+
+    for y in 0..size.y {
+        for z in 0..size.z {
+            for x in 0..size.x {
+                blocks.push(terrain.get_block(position.x + x, position.y + y, position.z + z));
+            }
+        }
+    }
+
+    blocks
+}
+
+fn chunk_generation(c: &mut Criterion) {
+    let settings = WorldGenSettings::default();
+    let terrain = NoiseTerrain::new(&settings);
+    // A whole column of the old 256 blocks high chunks
+    let size = IVec3::new(settings.chunk_width, 256, settings.chunk_width);
+
+    // Both ways have to build the same chunk, otherwise the comparison is meaningless
+    assert!(generate_per_block(&terrain, IVec3::ZERO, size) == terrain.generate(IVec3::ZERO, size));
+
+    let mut group = c.benchmark_group("chunk_generation");
+    // Generating a chunk per block takes a while, so keep the number of samples low
+    group.sample_size(10);
+
+    group.bench_function("per_block", |b| {
+        b.iter(|| generate_per_block(&terrain, black_box(IVec3::ZERO), size))
+    });
+
+    group.bench_function("per_column", |b| {
+        b.iter(|| terrain.generate(black_box(IVec3::ZERO), size))
+    });
+
+    // Sections of the streamed world, one with the surface in it and one far up in the sky
+    // The sky section doesn't need the height and biome of its columns, so it should be much faster
+    let section_size = IVec3::new(settings.chunk_width, SECTION_HEIGHT, settings.chunk_width);
+
+    group.bench_function("ground_section", |b| {
+        b.iter(|| terrain.generate(black_box(IVec3::new(0, 96, 0)), section_size))
+    });
+
+    group.bench_function("air_section", |b| {
+        b.iter(|| terrain.generate(black_box(IVec3::new(0, 1024, 0)), section_size))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, chunk_generation);
+criterion_main!(benches);
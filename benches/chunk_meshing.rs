@@ -0,0 +1,47 @@
+use bevy::prelude::*;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use my_bevy_game::world::{find_neighbors, generate_section, mesh_chunk, BiomeMap, BlockRegistry, MeshPass, MesherKind, NoiseTerrain, PendingWrites, VoxelWorld, WorldGenSettings, SECTION_HEIGHT};
+
+fn chunk_meshing(c: &mut Criterion) {
+    let settings = WorldGenSettings::default();
+    let biomes = BiomeMap::new(&settings);
+    let generator = NoiseTerrain::new(&settings);
+    let registry = BlockRegistry::default();
+    let mut world = VoxelWorld::new(&settings);
+    let mut pending = PendingWrites::default();
+
+    // The chunk in the middle with all of its neighbors, the sections around the ground level have the most faces
+    let ground_section : i32 = settings.ground_level.div_euclid(SECTION_HEIGHT);
+
+    for x in -1..=1 {
+        for z in -1..=1 {
+            for section_y in ground_section - 1..=ground_section + 1 {
+                let position = IVec3::new(x * settings.chunk_width, section_y * SECTION_HEIGHT, z * settings.chunk_width);
+                generate_section(position, &mut world, &mut pending, &generator, &biomes, &registry, &settings);
+            }
+        }
+    }
+
+    let chunk = world.chunk(IVec2::ZERO).unwrap();
+    let neighbors = find_neighbors(chunk, &world);
+
+    // Both meshers have to build the same mesh, otherwise the comparison is meaningless
+    let per_voxel = mesh_chunk(chunk, &neighbors, &registry, MeshPass::Opaque, MesherKind::PerVoxel);
+    let bitmask = mesh_chunk(chunk, &neighbors, &registry, MeshPass::Opaque, MesherKind::Bitmask);
+    assert!(per_voxel.positions.len() == bitmask.positions.len());
+
+    let mut group = c.benchmark_group("chunk_meshing");
+
+    group.bench_function("per_voxel", |b| {
+        b.iter(|| mesh_chunk(black_box(chunk), &neighbors, &registry, MeshPass::Opaque, MesherKind::PerVoxel))
+    });
+
+    group.bench_function("bitmask", |b| {
+        b.iter(|| mesh_chunk(black_box(chunk), &neighbors, &registry, MeshPass::Opaque, MesherKind::Bitmask))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, chunk_meshing);
+criterion_main!(benches);
@@ -0,0 +1,2 @@
+// The world generation lives in a library, so benchmarks can use it without starting the game
+pub mod world;
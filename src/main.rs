@@ -1,13 +1,18 @@
-use bevy::{prelude::*, DefaultPlugins, pbr::wireframe::{NoWireframe, Wireframe, WireframeColor, WireframeConfig, WireframePlugin},diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},};
+use bevy::{prelude::*, DefaultPlugins, pbr::wireframe::{WireframeConfig, WireframePlugin},diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},};
 use bevy_flycam::prelude::*;
-use world::WorldPlugin;
+use my_bevy_game::world::{ChunkLoader, WorldGenSettings, WorldPlugin};
 use bevy::window::PresentMode;
 use bevy::pbr::CascadeShadowConfigBuilder;
 use std::f32::consts::PI;
-mod world;
 
 #[bevy_main]
 fn main() {
+    // The world seed can be passed as first argument, so every run can generate a different world
+    let seed: u32 = std::env::args()
+        .nth(1)
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or_default();
+
     App::new()
     .add_plugins(DefaultPlugins.set(WindowPlugin {
         primary_window: Some(Window {
@@ -16,6 +21,10 @@ fn main() {
             ..default()
         }),
         ..default()
+    }).set(AssetPlugin {
+        // Reload the block definitions when they change, so blocks can be tweaked while the game runs
+        watch_for_changes_override: Some(true),
+        ..default()
     }))
         .add_plugins((
             FrameTimeDiagnosticsPlugin,
@@ -27,7 +36,12 @@ fn main() {
             default_color: Color::WHITE,
         })
         .add_plugins(NoCameraPlayerPlugin)
-        .add_plugins(WorldPlugin)
+        .add_plugins(WorldPlugin {
+            settings: WorldGenSettings {
+                seed,
+                ..default()
+            },
+        })
         .add_systems(Startup,  spawn_light)
         .add_systems(Update, animate_light_direction)
         .add_systems(Startup, setup)
@@ -40,7 +54,9 @@ fn setup(mut commands: Commands) {
             transform: Transform::from_xyz(5.0, 120.0, 5.5),
             ..default()
         },
-        FlyCam
+        FlyCam,
+        // The world gets generated around the camera while it flies around
+        ChunkLoader,
     ));
 }
 
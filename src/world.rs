@@ -1,657 +1,1986 @@
-use std::slice::Chunks;
-
 use bevy::prelude::*;
-use bevy::ui::debug;
-use rand::Rng;
-use bevy::render::render_resource::PrimitiveTopology;
-use noise::{NoiseFn, Perlin};
+use bevy::ecs::system::SystemParam;
 use bevy::utils::HashMap;
-use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use bevy::tasks::{block_on, AsyncComputeTaskPool, Task};
+use bevy::render::primitives::Frustum;
+use bevy::render::texture::{ImageLoaderSettings, ImageSampler};
+
+mod terrain_noise;
+mod biome;
+mod caves;
+mod ores;
+mod decoration;
+mod generator;
+mod noise_terrain;
+mod heightmap;
+mod block;
+mod block_asset;
+mod storage;
+mod direction;
+mod load_queue;
+mod greedy;
+mod mesher;
+mod bitmask;
+mod atlas;
+pub use terrain_noise::{noise_seed, NoiseStream, NoiseType, TerrainNoise};
+pub use biome::{Biome, BiomeMap, BiomeParams};
+pub use decoration::PendingWrites;
+pub use generator::{FlatLayer, FlatTerrain, GeneratorKind, TerrainGenerator, VoidTerrain, WorldGenerator};
+pub use noise_terrain::NoiseTerrain;
+pub use heightmap::{HeightmapError, HeightmapSettings, HeightmapTerrain};
+pub use block::{BlockDefinition, BlockId, BlockRegistry, BlockTextures};
+pub use storage::PalettedStorage;
+pub use direction::{neighbor_offsets, Direction, DIRECTIONS, HORIZONTAL_DIRECTIONS};
+pub use load_queue::{LoadQueue, LoaderView};
+pub use mesher::{mesh_chunk, ChunkMeshData, MeshPass, MesherKind, Neighbors};
+use mesher::NeighborSections;
+pub use atlas::{BlockAtlas, VoxelMaterial, ATLAS_COLUMNS, ATLAS_PATH, ATTRIBUTE_TILE, NO_TEXTURE};
+pub use block_asset::{BlockDefinitionData, BlockDefinitions, BlockDefinitionsChanged, BlockDefinitionsError, BlockDefinitionsHandle, BlockDefinitionsLoader};
+pub use block::{BLOCK_AIR, BLOCK_STONE, BLOCK_GRASS, BLOCK_DIRT, BLOCK_SAND, BLOCK_SANDSTONE, BLOCK_GRAVEL, BLOCK_SNOW, BLOCK_BEDROCK};
+pub use block::{BLOCK_COAL_ORE, BLOCK_IRON_ORE, BLOCK_GOLD_ORE, BLOCK_DIAMOND_ORE, BLOCK_WATER, BLOCK_LOG, BLOCK_LEAVES, BLOCK_TALL_GRASS, BLOCK_CACTUS};
+
+
+// Configuration of the world generation
+// Can be changed at startup via the WorldPlugin, so different worlds don't need a recompile
+#[derive(Resource, Clone)]
+pub struct WorldGenSettings {
+    // WORLD VARIABLES
+    pub seed: u32,
+    pub generator: GeneratorKind,
+
+    // CHUNK VARIABLES
+    pub chunk_width: i32,
+    pub mesher: MesherKind,
+
+    // TERRAIN VARIABLES
+    pub terrain_mode: TerrainMode,
+    pub octaves: usize,
+    pub lacunarity: f64,
+    pub persistence: f64,
+    pub noise_type: NoiseType,
+    pub ground_level: i32,
+    pub amplitude: i32,
+    pub scale: f64,
+    // Number of chunks loaded in every direction around a chunk loader
+    pub render_distance: i32,
+    // Chunks further away than this from every chunk loader get unloaded
+    // Bigger than the render distance, so chunks on the border don't get loaded and unloaded over and over
+    pub unload_distance: i32,
+    // Number of generated sections and of section meshes moved into the world per frame, so frame times stay smooth
+    pub max_uploads_per_frame: usize,
+    // Number of sections loaded above and below a chunk loader, chunks have no fixed height
+    pub vertical_render_distance: i32,
+    // Everything at and below this height is bedrock, None for a world without a bottom
+    pub bedrock_level: Option<i32>,
+
+    // WATER VARIABLES
+    // Every air block below the sea level gets filled with water
+    pub sea_level: i32,
+    pub river_scale: f64,
+    pub river_width: f64,
+    pub river_depth: i32,
+
+    // DENSITY VARIABLES
+    // Frequency of the 3D noise and how many blocks it can move the surface up or down
+    pub density_scale: f64,
+    pub density_strength: f64,
+
+    // CAVE VARIABLES
+    pub cave_scale: f64,
+    pub cheese_threshold: f64,
+    pub spaghetti_width: f64,
+
+    // BIOME VARIABLES
+    pub biome_scale: f64,
+}
 
+impl Default for WorldGenSettings {
+    fn default() -> Self {
+        WorldGenSettings {
+            seed: 0,
+            generator: GeneratorKind::Noise,
+            chunk_width: 32,
+            mesher: MesherKind::Bitmask,
+            terrain_mode: TerrainMode::Heightmap,
+            octaves: 4,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            noise_type: NoiseType::Standard,
+            ground_level: 100,
+            amplitude: 12,
+            scale: 0.05,
+            render_distance: 10,
+            unload_distance: 12,
+            max_uploads_per_frame: 32,
+            vertical_render_distance: 4,
+            bedrock_level: Some(0),
+            sea_level: 96,
+            river_scale: 0.003,
+            river_width: 0.04,
+            river_depth: 3,
+            density_scale: 0.03,
+            density_strength: 12.0,
+            cave_scale: 0.02,
+            cheese_threshold: 0.3,
+            spaghetti_width: 0.04,
+            biome_scale: 0.002,
+        }
+    }
+}
 
-use bevy::render::mesh::Indices;
-pub struct WorldPlugin;
+// How the shape of the terrain gets generated
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TerrainMode {
+    // 2D noise decides the height of every column, no caves or overhangs
+    Heightmap,
+    // 3D noise bends the terrain around the height of the column and caves get carved into it
+    Density,
+}
 
+#[derive(Default)]
+pub struct WorldPlugin {
+    pub settings: WorldGenSettings,
+}
 
-// BLOCK TYPES
-const BLOCK_AIR : i32 = 0;
-const BLOCK_SOLID : i32 = 1;
 
-// CHUNK VARIABLES
-const CHUNK_WIDTH : i32 = 32;
-const CHUNK_HEIGHT : i32 = 256;
+// Height of a chunk section, chunks are split into sections so uniform parts of them can be skipped
+pub const SECTION_HEIGHT : i32 = 32;
 
-// TERRAIN VARIABLES
-const OCTAVES : usize = 4;
-const GROUND_LEVEL : i32 = 100;
-const AMPLITUDE : i32 = 3;
-const SCALE : f64 = 0.05;
-const RENDER_DISTANCE : i32 = 20;
+// Cube of a chunk, every section gets its own mesh
+// Sections are generated one by one, so a chunk can reach as high and as deep as needed
+#[derive(Clone)]
+struct Section {
+    blocks: PalettedStorage,
+    // The blocks changed since the section was meshed the last time
+    dirty: bool,
+}
 
+impl Section {
+    // New sections are dirty, so they get meshed as soon as possible
+    fn new(blocks: &[BlockId]) -> Self {
+        Section { blocks: PalettedStorage::from_blocks(blocks), dirty: true }
+    }
 
-struct Block {
-    id: i32,
-    block_type: i32,
-}
+    // Sections with only invisible blocks like air have nothing to mesh
+    fn is_empty(&self, registry: &BlockRegistry) -> bool {
+        self.blocks.is_uniform() && !registry.get(self.blocks.get(0)).visible
+    }
 
-impl Block {
-    pub fn new(id: i32, block_type: i32) -> Self {
-        Block { id, block_type }
+    // Sections filled with a single opaque block hide all faces inside of them
+    fn is_full(&self, registry: &BlockRegistry) -> bool {
+        self.blocks.is_uniform() && MeshPass::Opaque.contains(registry, self.blocks.get(0))
     }
 }
 
-struct Chunk {
-    id: i32,
-    blocks: Vec<Block>,
+// Column of sections at a x, z position of the world
+pub struct Chunk {
+    // The generated sections, by their section y (world y / SECTION_HEIGHT)
+    sections: BTreeMap<i32, Section>,
     position: IVec2,
+    // Size of one section
+    size: IVec3,
+    // Height of every column, row by row: the y above its highest solid block, None if the generated sections have none
+    heightmap: Vec<Option<i32>>,
 }
 
 impl Chunk {
-    pub fn new(id: i32, size: IVec3, position: IVec2) -> Self {
-        let num_voxels: i32 = size.x * size.y * size.z;
-        let mut blocks: Vec<Block> = Vec::with_capacity(num_voxels as usize);
+    // Chunk without any sections, they get added by generate_section
+    pub fn new(position: IVec2, settings: &WorldGenSettings) -> Self {
+        let size: IVec3 = IVec3::new(settings.chunk_width, SECTION_HEIGHT, settings.chunk_width);
+        let heightmap: Vec<Option<i32>> = vec![None; (size.x * size.z) as usize];
+
+        Self { sections: BTreeMap::new(), position, size, heightmap }
+    }
+
+    // Add a generated section
+    fn insert_section(&mut self, section_y: i32, section: Section, registry: &BlockRegistry) {
+        let layer_size : i32 = self.size.x * self.size.z;
+
+        // Remember the surface of every column, so it can be looked up later without going through the blocks
+        for column in 0..layer_size {
+            let top = (0..self.size.y)
+                .rev()
+                .find(|y| registry.get(section.blocks.get((column + y * layer_size) as usize)).solid);
+
+            if let Some(y) = top {
+                let height = Some(section_y * SECTION_HEIGHT + y + 1);
+                self.heightmap[column as usize] = self.heightmap[column as usize].max(height);
+            }
+        }
+
+        // The faces of the sections above and below next to the new section can change
+        for neighbor_y in [section_y - 1, section_y + 1] {
+            if let Some(neighbor) = self.sections.get_mut(&neighbor_y) {
+                neighbor.dirty = true;
+            }
+        }
+
+        self.sections.insert(section_y, section);
+    }
+
+    // Remove a section which left the loaded range
+    // The columns which had their top in it get the height of the sections below, as if it was never generated
+    fn remove_section(&mut self, section_y: i32, registry: &BlockRegistry) {
+        if self.sections.remove(&section_y).is_none() {
+            return;
+        }
+
+        let bottom : i32 = section_y * SECTION_HEIGHT;
+
+        for z in 0..self.size.z {
+            for x in 0..self.size.x {
+                let column : usize = (x + z * self.size.x) as usize;
+
+                if self.heightmap[column].is_some_and(|height| height > bottom && height <= bottom + SECTION_HEIGHT) {
+                    self.heightmap[column] = self.height_below(IVec3::new(x, bottom, z), registry);
+                }
+            }
+        }
+
+        // The top faces of the section below are open to the sky now
+        for neighbor_y in [section_y - 1, section_y + 1] {
+            if let Some(neighbor) = self.sections.get_mut(&neighbor_y) {
+                neighbor.dirty = true;
+            }
+        }
+    }
+
+    pub fn position(&self) -> IVec2 {
+        self.position
+    }
 
-        let mut block_ids : i32 = 0; 
-    
+    pub fn has_section(&self, section_y: i32) -> bool {
+        self.sections.contains_key(&section_y)
+    }
+
+    // Section y and the index inside of the section of the block at the local x, z and world y
+    fn block_index(&self, position: IVec3) -> (i32, usize) {
+        let section_y : i32 = position.y.div_euclid(SECTION_HEIGHT);
+        let y : i32 = position.y.rem_euclid(SECTION_HEIGHT);
+
+        (section_y, (position.x + position.z * self.size.x + y * self.size.x * self.size.z) as usize)
+    }
+
+    // Get the block at the local x, z and world y, None if its section isn't generated yet
+    pub fn get_block(&self, position: IVec3) -> Option<BlockId> {
+        let (section_y, index) = self.block_index(position);
+
+        self.sections.get(&section_y).map(|section| section.blocks.get(index))
+    }
 
-        let mut noises: Vec<Perlin> = Vec::with_capacity(OCTAVES);
+    // Change the block at the local x, z and world y, only the sections next to the block need to be meshed again
+    // Returns false if the section of the block isn't generated yet
+    pub fn set_block(&mut self, position: IVec3, block: BlockId, registry: &BlockRegistry) -> bool {
+        let (section_y, index) = self.block_index(position);
 
-        for i in 0..OCTAVES {
-            let perlin = Perlin::new(i as u32);
-            noises.push(perlin);
+        let Some(section) = self.sections.get_mut(&section_y) else { return false };
+
+        section.blocks.set(index, block);
+        section.dirty = true;
+
+        // Blocks on the bottom or top layer of a section can hide faces of the section below or above
+        let y : i32 = position.y.rem_euclid(SECTION_HEIGHT);
+        let neighbor_y : Option<i32> = match y {
+            0 => Some(section_y - 1),
+            _ if y == SECTION_HEIGHT - 1 => Some(section_y + 1),
+            _ => None,
+        };
+
+        if let Some(neighbor) = neighbor_y.and_then(|neighbor_y| self.sections.get_mut(&neighbor_y)) {
+            neighbor.dirty = true;
         }
 
-        for i in 0..num_voxels {
-            let x: i32 = i % CHUNK_WIDTH;
-            let z: i32 = (i % (CHUNK_WIDTH * CHUNK_WIDTH)) / CHUNK_WIDTH;
-            let y: i32 = i / (CHUNK_WIDTH * CHUNK_WIDTH);
-        
-            blocks.push(Block::new(block_ids, get_block(x + position.x, y, z + position.y, &mut noises)));
-            block_ids += 1;
+        // Only removing the top block of the column needs a search for the next solid block below it
+        let column : usize = (position.x + position.z * self.size.x) as usize;
+
+        if registry.get(block).solid {
+            self.heightmap[column] = self.heightmap[column].max(Some(position.y + 1));
+        } else if self.heightmap[column] == Some(position.y + 1) {
+            self.heightmap[column] = self.height_below(position, registry);
         }
 
-        Self { id, blocks, position }
+        true
+    }
+
+    // Height of the column at the local x, z when only the blocks below the world y are counted
+    fn height_below(&self, position: IVec3, registry: &BlockRegistry) -> Option<i32> {
+        let bottom : i32 = self.sections.keys().next().map_or(position.y, |section_y| section_y * SECTION_HEIGHT);
+
+        (bottom..position.y)
+            .rev()
+            .find(|y| self.get_block(IVec3::new(position.x, *y, position.z)).is_some_and(|block| registry.get(block).solid))
+            .map(|y| y + 1)
+    }
+
+    // Get the height of the column at the local x, z of the chunk
+    pub fn surface_height(&self, x: i32, z: i32) -> Option<i32> {
+        self.heightmap[(x + z * self.size.x) as usize]
     }
 }
 
-// Get the value of the given 2D noise at x, z and choose the corresponding block type
-fn get_block(x: i32, y: i32, z: i32, noises: &mut Vec<Perlin>) -> i32 {
-    let mut value : f64 = 0.0;
+// All loaded chunks of the world, by the x, z position of their lowest corner
+// Gameplay systems read and change blocks through it with world coordinates
+#[derive(Resource)]
+pub struct VoxelWorld {
+    chunks: HashMap<IVec2, Chunk>,
+    chunk_width: i32,
+}
+
+impl VoxelWorld {
+    pub fn new(settings: &WorldGenSettings) -> Self {
+        VoxelWorld { chunks: HashMap::new(), chunk_width: settings.chunk_width }
+    }
+
+    // Position of the chunk which contains the world position
+    // div_euclid rounds down, so -1 is in the chunk at -chunk_width and not in the one at 0
+    pub fn chunk_position(&self, position: IVec3) -> IVec2 {
+        IVec2::new(
+            position.x.div_euclid(self.chunk_width) * self.chunk_width,
+            position.z.div_euclid(self.chunk_width) * self.chunk_width,
+        )
+    }
+
+    // Position inside of its chunk, x and z are in 0..chunk_width and y stays the world y
+    pub fn local_position(&self, position: IVec3) -> IVec3 {
+        IVec3::new(position.x.rem_euclid(self.chunk_width), position.y, position.z.rem_euclid(self.chunk_width))
+    }
+
+    // World position of a position inside of the chunk at the chunk position
+    pub fn world_position(&self, chunk_position: IVec2, local_position: IVec3) -> IVec3 {
+        local_position + IVec3::new(chunk_position.x, 0, chunk_position.y)
+    }
+
+    pub fn chunk(&self, chunk_position: IVec2) -> Option<&Chunk> {
+        self.chunks.get(&chunk_position)
+    }
+
+    pub fn chunk_mut(&mut self, chunk_position: IVec2) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&chunk_position)
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks.values()
+    }
+
+    pub fn chunks_mut(&mut self) -> impl Iterator<Item = &mut Chunk> {
+        self.chunks.values_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    // Get the block at the world position, None if it isn't loaded
+    pub fn get_block(&self, position: IVec3) -> Option<BlockId> {
+        self.chunk(self.chunk_position(position))?.get_block(self.local_position(position))
+    }
+
+    // Change the block at the world position and mark the sections which need to be meshed again
+    // Returns false if the block isn't loaded
+    pub fn set_block(&mut self, position: IVec3, block: BlockId, registry: &BlockRegistry) -> bool {
+        let chunk_position : IVec2 = self.chunk_position(position);
+        let local : IVec3 = self.local_position(position);
+
+        if !self.chunks.get_mut(&chunk_position).is_some_and(|chunk| chunk.set_block(local, block, registry)) {
+            return false;
+        }
+
+        // Blocks on the border of a chunk can hide faces of the neighbor chunks and darken their corners
+        let mut sections: Vec<(IVec2, i32)> = Vec::new();
+
+        for offset in neighbor_offsets() {
+            let neighbor : IVec3 = position + offset;
+            let key = (self.chunk_position(neighbor), neighbor.y.div_euclid(SECTION_HEIGHT));
+
+            if !sections.contains(&key) {
+                sections.push(key);
+            }
+        }
+
+        for (chunk_position, section_y) in sections {
+            if let Some(section) = self.chunks.get_mut(&chunk_position).and_then(|chunk| chunk.sections.get_mut(&section_y)) {
+                section.dirty = true;
+            }
+        }
+
+        true
+    }
+
+    // Mark the section and all 26 sections around it to be meshed again, the ones in the neighbor chunks included
+    fn mark_section_and_neighbors_dirty(&mut self, chunk_position: IVec2, section_y: i32) {
+        for offset in neighbor_offsets().chain([IVec3::ZERO]) {
+            let neighbor_position : IVec2 = chunk_position + IVec2::new(offset.x, offset.z) * self.chunk_width;
 
-    for noise in noises {
-        value += noise.get([x as f64 * SCALE, z as f64 * SCALE]);
+            if let Some(section) = self.chunks.get_mut(&neighbor_position).and_then(|chunk| chunk.sections.get_mut(&(section_y + offset.y))) {
+                section.dirty = true;
+            }
+        }
     }
+}
 
-    let surface_y : i32 = (GROUND_LEVEL as f64 + (value * AMPLITUDE as f64)) as i32;
-    
-    if y < surface_y {
-        return BLOCK_SOLID;
+// Blocks and decorations of a freshly generated section, which isn't part of the world yet
+struct GeneratedSection {
+    position: IVec3,
+    section: Section,
+    writes: Vec<(IVec3, BlockId)>,
+}
+
+// Generate the terrain of the section with its lowest corner at the world position and run the decoration pass on it
+// Doesn't touch the world, so it can run on a background thread
+fn build_section(
+    position: IVec3,
+    generator: &dyn TerrainGenerator,
+    biomes: &BiomeMap,
+    registry: &BlockRegistry,
+    settings: &WorldGenSettings,
+) -> GeneratedSection {
+    let size: IVec3 = IVec3::new(settings.chunk_width, SECTION_HEIGHT, settings.chunk_width);
+
+    // One layer more than the section, so the decoration pass knows if the top blocks of the section are covered
+    let mut blocks: Vec<BlockId> = generator.generate(position, size + IVec3::Y);
+    assert_eq!(blocks.len(), (size.x * (size.y + 1) * size.z) as usize, "terrain generator returned the wrong number of blocks");
+
+    let writes : Vec<(IVec3, BlockId)> = if generator.has_decorations() {
+        decoration::decorate(&blocks, position, size, biomes, registry, settings)
     } else {
-        return BLOCK_AIR;
+        Vec::new()
+    };
+
+    blocks.truncate((size.x * size.y * size.z) as usize);
+
+    GeneratedSection { position, section: Section::new(&blocks), writes }
+}
+
+// Add a generated section to the world
+// Decorations that reach into other sections are written into them if they already exist,
+// otherwise they wait in the pending writes until that section gets generated, so trees are never cut in half
+// Returns true if it is the first section of its chunk
+fn insert_generated_section(
+    generated: GeneratedSection,
+    world: &mut VoxelWorld,
+    pending: &mut PendingWrites,
+    registry: &BlockRegistry,
+    settings: &WorldGenSettings,
+) -> bool {
+    let GeneratedSection { position, section, writes } = generated;
+    let chunk_position = IVec2::new(position.x, position.z);
+    let section_y : i32 = position.y.div_euclid(SECTION_HEIGHT);
+    let is_new : bool = world.chunk(chunk_position).is_none();
+
+    let chunk = world.chunks.entry(chunk_position).or_insert_with(|| Chunk::new(chunk_position, settings));
+    chunk.insert_section(section_y, section, registry);
+    pending.apply(chunk, position, registry);
+
+    // The faces and the corner shading of the sections around the new section can change, the diagonal ones included
+    for offset in neighbor_offsets().filter(|offset| offset.x != 0 || offset.z != 0) {
+        if let Some(neighbor) = world.chunks.get_mut(&(chunk_position + IVec2::new(offset.x, offset.z) * settings.chunk_width)) {
+            if let Some(section) = neighbor.sections.get_mut(&(section_y + offset.y)) {
+                section.dirty = true;
+            }
+        }
+    }
+
+    for (world_position, block_type) in writes {
+        let target : IVec3 = decoration::section_position_of(world_position, settings);
+
+        // Writes into the section itself get placed again whenever it is generated, so only the others need to be kept
+        if target != position {
+            pending.push(target, position, world_position, block_type);
+        }
+
+        if let Some(chunk) = world.chunks.get_mut(&IVec2::new(target.x, target.z)) {
+            if chunk.has_section(target.y.div_euclid(SECTION_HEIGHT)) {
+                decoration::place_block(chunk, world_position, block_type, registry);
+            }
+        }
+    }
+
+    is_new
+}
+
+// Generate a section and add it to the world right away, on the thread that calls it
+// Used where a few sections are needed at once, like tests and benchmarks, the world streaming generates them in the background
+pub fn generate_section(
+    position: IVec3,
+    world: &mut VoxelWorld,
+    pending: &mut PendingWrites,
+    generator: &dyn TerrainGenerator,
+    biomes: &BiomeMap,
+    registry: &BlockRegistry,
+    settings: &WorldGenSettings,
+) {
+    let generated = build_section(position, generator, biomes, registry, settings);
+    insert_generated_section(generated, world, pending, registry, settings);
+}
+
+// Remove the chunk from the world, the decorations it placed in other chunks stay where they are
+// The writes of its sections get dropped, they are made again when the chunk gets generated again
+fn unload_chunk(position: IVec2, world: &mut VoxelWorld, pending: &mut PendingWrites) {
+    world.chunks.remove(&position);
+    pending.remove_column(position);
+}
+
+// Remove the section with its lowest corner at the world position, like unload_chunk does with whole chunks
+// Used for the sections above and below the loaded range, the rest of the chunk stays loaded
+fn unload_section(position: IVec3, world: &mut VoxelWorld, pending: &mut PendingWrites, registry: &BlockRegistry) {
+    if let Some(chunk) = world.chunk_mut(IVec2::new(position.x, position.z)) {
+        chunk.remove_section(position.y.div_euclid(SECTION_HEIGHT), registry);
     }
+
+    pending.remove_section(position);
+}
+
+
+// Materials shared by the meshes of all chunks
+#[derive(Resource)]
+struct ChunkMaterials {
+    opaque: Handle<VoxelMaterial>,
+    transparent: Handle<VoxelMaterial>,
+}
+
+// Entity of a loaded chunk, the meshes of its sections are spawned as its children
+#[derive(Component)]
+pub struct ChunkEntity {
+    pub position: IVec2,
+}
+
+// Mesh entities of every section by chunk position and section y, spawned as children of their chunk, so they can be replaced without going through all meshes
+#[derive(Resource, Default)]
+struct SectionEntities {
+    entities: HashMap<(IVec2, i32), Vec<Entity>>,
+}
+
+// The world gets loaded around every entity with this component, for example the camera
+#[derive(Component)]
+pub struct ChunkLoader;
+
+// Opaque and transparent mesh of a section, None if the section has no visible faces of them
+struct SectionMeshes {
+    opaque: Option<ChunkMeshData>,
+    transparent: Option<ChunkMeshData>,
+}
+
+// Generation and meshing work running on the AsyncComputeTaskPool, so the main thread never has to wait for it
+// Dropping a task cancels it
+#[derive(Resource, Default)]
+struct ChunkTasks {
+    generating: HashMap<IVec3, Task<GeneratedSection>>,
+    // By chunk position and section y
+    meshing: HashMap<(IVec2, i32), Task<SectionMeshes>>,
 }
 
+// Copy of everything a background task reads, shared by all tasks started in the same frame
+struct TaskContext {
+    generator: Arc<dyn TerrainGenerator>,
+    biomes: BiomeMap,
+    registry: BlockRegistry,
+    settings: WorldGenSettings,
+}
+
+// The loaded world and the work running on it
+#[derive(SystemParam)]
+struct WorldState<'w> {
+    world: ResMut<'w, VoxelWorld>,
+    pending: ResMut<'w, PendingWrites>,
+    tasks: ResMut<'w, ChunkTasks>,
+}
+
+// Everything the background tasks get a copy of
+#[derive(SystemParam)]
+struct TaskResources<'w> {
+    generator: Res<'w, WorldGenerator>,
+    biomes: Res<'w, BiomeMap>,
+    registry: Res<'w, BlockRegistry>,
+    settings: Res<'w, WorldGenSettings>,
+}
+
+impl TaskResources<'_> {
+    // One copy per frame, shared by all tasks started in it
+    fn context(&self) -> Arc<TaskContext> {
+        Arc::new(TaskContext {
+            generator: self.generator.0.clone(),
+            biomes: self.biomes.clone(),
+            registry: self.registry.clone(),
+            settings: self.settings.clone(),
+        })
+    }
+}
+
+// Position and view frustum of every chunk loader, the frustum only exists on cameras
+fn loader_views(loader_query: &Query<(&GlobalTransform, Option<&Frustum>), With<ChunkLoader>>) -> Vec<LoaderView> {
+    loader_query
+        .iter()
+        .map(|(transform, frustum)| LoaderView { position: transform.translation(), frustum: frustum.copied() })
+        .collect()
+}
+
+// Number of tasks of each kind that run at the same time, the rest waits in the load queue
+// A few more than there are threads, so no thread has to wait for the next frame to get work
+fn max_running_tasks() -> usize {
+    AsyncComputeTaskPool::get().thread_num().max(1) * 2
+}
+
+fn setup_chunk_materials(mut commands: Commands, mut materials: ResMut<Assets<VoxelMaterial>>, asset_server: Res<AssetServer>) {
+    // The tiles are pixel art, so they stay sharp instead of getting blurred
+    let atlas: Handle<Image> = asset_server.load_with_settings(ATLAS_PATH, |settings: &mut ImageLoaderSettings| {
+        settings.sampler = ImageSampler::nearest();
+    });
+
+    // The colors and tiles of the blocks are stored in the vertices, so all chunks can share one white material with the atlas
+    commands.insert_resource(ChunkMaterials {
+        opaque: materials.add(VoxelMaterial {
+            base: Color::WHITE.into(),
+            extension: BlockAtlas::new(atlas.clone()),
+        }),
+        transparent: materials.add(VoxelMaterial {
+            base: StandardMaterial {
+                base_color: Color::WHITE,
+                alpha_mode: AlphaMode::Blend,
+                ..default()
+            },
+            extension: BlockAtlas::new(atlas),
+        }),
+    });
+}
 
-// Generate all chunks in render distance
-// A chunk combines multiple voxels and turns them into one mesh
-fn spawn_chunks(
+// Start generating the sections in render distance around every chunk loader and unload the chunks that are too far away from all of them
+// The missing sections closest to the loaders get generated first
+fn stream_chunks(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    loader_query: Query<(&GlobalTransform, Option<&Frustum>), With<ChunkLoader>>,
+    chunk_query: Query<(Entity, &ChunkEntity)>,
+    state: WorldState,
+    resources: TaskResources,
+    mut section_entities: ResMut<SectionEntities>,
 ) {
-    let mut chunks: Vec<Chunk> = Vec::new();
-
-    let mut chunk_ids : i32 = 0;
-
-
-    for x in 0..RENDER_DISTANCE {
-        for z in 0..RENDER_DISTANCE {
-            let position = IVec2::new(x as i32 * CHUNK_WIDTH, z as i32 * CHUNK_WIDTH);
-            let size: IVec3 = IVec3::new(CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_WIDTH);
-            let chunk = Chunk::new(chunk_ids, size, position);
-            chunk_ids += 1;
-            
-            chunks.push(chunk);
-        }
-    }
-
-    for chunk in &chunks{
-        // Find neighboring chunks via position (distance from each other) and put it into a HashMap
-        let mut neighbors_by_direction: HashMap<&'static str, &Chunk> = HashMap::new();
-
-        chunks.iter().for_each(|other_chunk| {
-        let distance_x = chunk.position.x - other_chunk.position.x;
-        let distance_y = chunk.position.y - other_chunk.position.y;
-        
-        if !(distance_x == 0 && distance_y == 0) {
-
-            if distance_x == CHUNK_WIDTH && distance_y == 0 {
-                neighbors_by_direction.entry("left").or_insert(other_chunk);
-            } else 
-            if distance_x == 0 && distance_y == CHUNK_WIDTH {
-                neighbors_by_direction.entry("top").or_insert(other_chunk);
-            }else 
-            if distance_x == -1*CHUNK_WIDTH && distance_y == 0 {
-                neighbors_by_direction.entry("right").or_insert(other_chunk);
-            }else 
-            if distance_x == 0 && distance_y <= -1*CHUNK_WIDTH {
-                neighbors_by_direction.entry("down").or_insert(other_chunk);
+    let WorldState { mut world, mut pending, mut tasks } = state;
+    let (settings, registry) = (&resources.settings, &resources.registry);
+    let views: Vec<LoaderView> = loader_views(&loader_query);
+
+    // Chunk x, z and section y of every loader, in chunks and not in blocks
+    let centers: Vec<IVec3> = views
+        .iter()
+        .map(|view| {
+            let position : IVec3 = view.position.floor().as_ivec3();
+            IVec3::new(position.x.div_euclid(settings.chunk_width), position.y.div_euclid(SECTION_HEIGHT), position.z.div_euclid(settings.chunk_width))
+        })
+        .collect();
+
+    // Sections get the same margin above and below the loaded range as chunks get around it
+    let vertical_unload_distance : i32 = settings.vertical_render_distance + settings.unload_distance - settings.render_distance;
+
+    let column_in_range = |chunk_position: IVec2, center: &IVec3| -> bool {
+        let position : IVec2 = chunk_position / settings.chunk_width;
+
+        (position.x - center.x).abs() <= settings.unload_distance && (position.y - center.z).abs() <= settings.unload_distance
+    };
+
+    let in_range = |chunk_position: IVec2| -> bool {
+        centers.iter().any(|center| column_in_range(chunk_position, center))
+    };
+
+    let section_in_range = |chunk_position: IVec2, section_y: i32| -> bool {
+        centers.iter().any(|center| column_in_range(chunk_position, center) && (section_y - center.y).abs() <= vertical_unload_distance)
+    };
+
+    // Despawning the chunk entity also despawns its section meshes, which frees their mesh assets
+    // The materials are shared by all chunks, so they stay
+    for (entity, chunk_entity) in chunk_query.iter() {
+        if !in_range(chunk_entity.position) {
+            commands.entity(entity).despawn_recursive();
+            section_entities.entities.retain(|(chunk_position, _), _| *chunk_position != chunk_entity.position);
+            unload_chunk(chunk_entity.position, &mut world, &mut pending);
+            continue;
+        }
+
+        // Only the sections too far above or below every loader get unloaded
+        let Some(chunk) = world.chunk(chunk_entity.position) else { continue };
+        let far_sections: Vec<i32> = chunk.sections.keys().copied().filter(|section_y| !section_in_range(chunk_entity.position, *section_y)).collect();
+
+        for section_y in far_sections {
+            for mesh_entity in section_entities.entities.remove(&(chunk_entity.position, section_y)).into_iter().flatten() {
+                commands.entity(mesh_entity).despawn_recursive();
             }
+
+            let position = IVec3::new(chunk_entity.position.x, section_y * SECTION_HEIGHT, chunk_entity.position.y);
+            unload_section(position, &mut world, &mut pending, registry);
         }
-    });
+    }
 
-        let cube_mesh: Handle<Mesh> = create_cube_mesh(&mut meshes, &chunk, &mut neighbors_by_direction);
-        let random_hue: f32 = rand::thread_rng().gen_range(0.5..=1.0);
-        let material = materials.add(Color::rgb(0.0, 0.0, random_hue).into());
-        let cube = PbrBundle {
-            mesh: cube_mesh,
-            material: material.clone(),
-            transform: Transform::from_xyz(chunk.position.x as f32 , 0 as f32, chunk.position.y as f32),
-            ..default()
-        };
-        commands.spawn(cube);
+    // The results of tasks for sections which are out of range again aren't needed anymore
+    tasks.generating.retain(|position, _| section_in_range(IVec2::new(position.x, position.z), position.y.div_euclid(SECTION_HEIGHT)));
+    tasks.meshing.retain(|(chunk_position, section_y), _| section_in_range(*chunk_position, *section_y));
+
+    // The queue gets built again every frame, so it follows the loaders and forgets sections which left the range
+    let mut queue = LoadQueue::new(&views, IVec3::new(settings.chunk_width, SECTION_HEIGHT, settings.chunk_width));
+
+    for center in centers.iter() {
+        for x in center.x - settings.render_distance..=center.x + settings.render_distance {
+            for z in center.z - settings.render_distance..=center.z + settings.render_distance {
+                let chunk_position = IVec2::new(x, z) * settings.chunk_width;
+
+                for section_y in center.y - settings.vertical_render_distance..=center.y + settings.vertical_render_distance {
+                    let position = IVec3::new(chunk_position.x, section_y * SECTION_HEIGHT, chunk_position.y);
+
+                    if world.chunk(chunk_position).is_some_and(|chunk| chunk.has_section(section_y)) || tasks.generating.contains_key(&position) {
+                        continue;
+                    }
+
+                    queue.push(position);
+                }
+            }
+        }
+    }
+
+    if queue.is_empty() {
+        return;
+    }
+
+    let pool = AsyncComputeTaskPool::get();
+    let context : Arc<TaskContext> = resources.context();
+
+    while tasks.generating.len() < max_running_tasks() {
+        // Two loaders close to each other can push the same section twice
+        let Some(position) = queue.pop() else { break };
+
+        if tasks.generating.contains_key(&position) {
+            continue;
+        }
+
+        let context = context.clone();
+        let task = pool.spawn(async move {
+            build_section(position, context.generator.as_ref(), &context.biomes, &context.registry, &context.settings)
+        });
+
+        tasks.generating.insert(position, task);
+    }
+}
+
+// Add the finished sections to the world, the upload budget keeps a lot of finished tasks from slowing down a single frame
+fn insert_generated_sections(
+    mut commands: Commands,
+    mut tasks: ResMut<ChunkTasks>,
+    registry: Res<BlockRegistry>,
+    settings: Res<WorldGenSettings>,
+    mut pending: ResMut<PendingWrites>,
+    mut world: ResMut<VoxelWorld>,
+) {
+    let finished: Vec<IVec3> = tasks.generating
+        .iter()
+        .filter(|(_, task)| task.is_finished())
+        .map(|(position, _)| *position)
+        .take(settings.max_uploads_per_frame)
+        .collect();
+
+    for position in finished {
+        let generated : GeneratedSection = block_on(tasks.generating.remove(&position).unwrap());
+
+        // The sections of new chunks are dirty, so they get their meshes from queue_dirty_sections
+        if insert_generated_section(generated, &mut world, &mut pending, &registry, &settings) {
+            commands.spawn((
+                SpatialBundle::from_transform(Transform::from_xyz(position.x as f32, 0.0, position.z as f32)),
+                ChunkEntity { position: IVec2::new(position.x, position.z) },
+            ));
+        }
+    }
+}
+
+// Look up the eight chunks around the chunk by their position
+pub fn find_neighbors<'a>(chunk: &Chunk, world: &'a VoxelWorld) -> Neighbors<'a> {
+    neighbor_offsets()
+        .filter(|offset| offset.y == 0)
+        .filter_map(|offset| {
+            let offset = IVec2::new(offset.x, offset.z);
+            world.chunk(chunk.position + offset * world.chunk_width).map(|neighbor| (offset, neighbor))
+        })
+        .collect()
+}
+
+// Get the 26 sections around the section at section y of the chunk, in the same column and in the neighbor chunks
+// Sections which aren't loaded are left out
+fn find_neighbor_sections<'a>(chunk: &'a Chunk, section_y: i32, neighbor_chunks: &Neighbors<'a>) -> NeighborSections<'a> {
+    neighbor_offsets()
+        .filter_map(|offset| {
+            let column : &Chunk = if offset.x == 0 && offset.z == 0 { chunk } else { neighbor_chunks.get(&IVec2::new(offset.x, offset.z))? };
+            column.sections.get(&(section_y + offset.y)).map(|section| (offset, section))
+        })
+        .collect()
+}
+
+// Check if all faces of the section are hidden, because it is full and surrounded by full sections
+fn is_section_hidden(section: &Section, neighbor_sections: &NeighborSections, registry: &BlockRegistry) -> bool {
+    // The mesher never adds faces towards sections which don't exist, except for the top faces which are open to the sky
+    section.is_full(registry)
+        && neighbor_sections.contains_key(&IVec3::Y)
+        && DIRECTIONS
+            .iter()
+            .filter_map(|direction| neighbor_sections.get(&direction.offset()))
+            .all(|neighbor| neighbor.is_full(registry))
+}
+
+// Build the opaque and the transparent mesh of a section, if it has any blocks of them
+fn create_section_meshes(
+    section: &Section,
+    neighbor_sections: &NeighborSections,
+    registry: &BlockRegistry,
+    settings: &WorldGenSettings,
+) -> SectionMeshes {
+    if section.is_empty(registry) || is_section_hidden(section, neighbor_sections, registry) {
+        return SectionMeshes { opaque: None, transparent: None };
+    }
+
+    let palette : &[BlockId] = section.blocks.palette();
+    let size = IVec3::new(settings.chunk_width, SECTION_HEIGHT, settings.chunk_width);
+
+    let opaque: Option<ChunkMeshData> = palette
+        .iter()
+        .any(|block| MeshPass::Opaque.contains(registry, *block))
+        .then(|| mesher::mesh_section(section, neighbor_sections, registry, size, MeshPass::Opaque, settings.mesher));
+
+    // Transparent blocks like water get their own mesh which is rendered after the terrain
+    let transparent: Option<ChunkMeshData> = palette
+        .iter()
+        .any(|block| MeshPass::Transparent.contains(registry, *block))
+        .then(|| mesher::mesh_section(section, neighbor_sections, registry, size, MeshPass::Transparent, settings.mesher));
+
+    SectionMeshes { opaque, transparent }
+}
+
+// Spawn the meshes of the section as children of its chunk and return their entities
+fn spawn_section_meshes(
+    chunk_entity: &mut ChildBuilder,
+    section: i32,
+    opaque_mesh: Option<Handle<Mesh>>,
+    transparent_mesh: Option<Handle<Mesh>>,
+    chunk_materials: &ChunkMaterials,
+) -> Vec<Entity> {
+    let transform = Transform::from_xyz(0.0, (section * SECTION_HEIGHT) as f32, 0.0);
+
+    let meshes = [(opaque_mesh, &chunk_materials.opaque), (transparent_mesh, &chunk_materials.transparent)];
+
+    meshes
+        .into_iter()
+        .filter_map(|(mesh, material)| {
+            let mesh = mesh?;
+
+            Some(chunk_entity.spawn(MaterialMeshBundle {
+                mesh,
+                material: material.clone(),
+                transform,
+                ..default()
+            }).id())
+        })
+        .collect()
+}
+
+// Mark the sections which contain a block whose definition changed, so they get meshed again
+fn mark_changed_blocks_dirty(mut events: EventReader<BlockDefinitionsChanged>, mut world: ResMut<VoxelWorld>) {
+    let changed: Vec<BlockId> = events.read().flat_map(|event| event.blocks.iter().copied()).collect();
+
+    if changed.is_empty() {
+        return;
+    }
+
+    let changed_sections: Vec<(IVec2, i32)> = world.chunks()
+        .flat_map(|chunk| chunk.sections.iter().map(|(section_y, section)| (chunk.position(), *section_y, section)))
+        .filter(|(_, _, section)| section.blocks.palette().iter().any(|block| changed.contains(block)))
+        .map(|(chunk_position, section_y, _)| (chunk_position, section_y))
+        .collect();
+
+    // The faces and the corner shading of the sections next to it can change too, for example if a block became transparent
+    for (chunk_position, section_y) in changed_sections {
+        world.mark_section_and_neighbors_dirty(chunk_position, section_y);
+    }
+}
+
+// Start meshing the dirty sections on the task pool, the ones closest to the chunk loaders first
+// The task gets copies of the section and its neighbors, so the world can keep changing while it runs
+fn queue_dirty_sections(
+    loader_query: Query<(&GlobalTransform, Option<&Frustum>), With<ChunkLoader>>,
+    chunk_query: Query<&ChunkEntity>,
+    state: WorldState,
+    resources: TaskResources,
+) {
+    let WorldState { mut world, mut tasks, .. } = state;
+    let settings : &WorldGenSettings = &resources.settings;
+    let views: Vec<LoaderView> = loader_views(&loader_query);
+    let mut queue = LoadQueue::new(&views, IVec3::new(settings.chunk_width, SECTION_HEIGHT, settings.chunk_width));
+
+    for chunk_entity in chunk_query.iter() {
+        let Some(chunk) = world.chunk(chunk_entity.position) else { continue };
+
+        for (section_y, section) in chunk.sections.iter() {
+            // Sections that change while they are meshed stay dirty and get meshed again afterwards
+            if section.dirty && !tasks.meshing.contains_key(&(chunk.position, *section_y)) {
+                queue.push(IVec3::new(chunk.position.x, section_y * SECTION_HEIGHT, chunk.position.y));
+            }
+        }
     }
 
+    if queue.is_empty() {
+        return;
+    }
+
+    let pool = AsyncComputeTaskPool::get();
+    let context : Arc<TaskContext> = resources.context();
+
+    while tasks.meshing.len() < max_running_tasks() {
+        let Some(position) = queue.pop() else { break };
+
+        let chunk_position = IVec2::new(position.x, position.z);
+        let section_y : i32 = position.y.div_euclid(SECTION_HEIGHT);
+
+        let Some(chunk) = world.chunk(chunk_position) else { continue };
+
+        let section : Section = chunk.sections[&section_y].clone();
+        let neighbors: Vec<(IVec3, Section)> = find_neighbor_sections(chunk, section_y, &find_neighbors(chunk, &world))
+            .into_iter()
+            .map(|(offset, neighbor)| (offset, neighbor.clone()))
+            .collect();
+
+        let context = context.clone();
+        let task = pool.spawn(async move {
+            let neighbor_sections: NeighborSections = neighbors.iter().map(|(offset, neighbor)| (*offset, neighbor)).collect();
+            create_section_meshes(&section, &neighbor_sections, &context.registry, &context.settings)
+        });
+
+        tasks.meshing.insert((chunk_position, section_y), task);
+
+        if let Some(section) = world.chunk_mut(chunk_position).and_then(|chunk| chunk.sections.get_mut(&section_y)) {
+            section.dirty = false;
+        }
+    }
 }
 
+// Replace the old meshes of the sections with the finished ones, at most max_uploads_per_frame per frame
+fn upload_section_meshes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut tasks: ResMut<ChunkTasks>,
+    chunk_query: Query<(Entity, &ChunkEntity)>,
+    mut section_entities: ResMut<SectionEntities>,
+    chunk_materials: Res<ChunkMaterials>,
+    settings: Res<WorldGenSettings>,
+) {
+    let finished: Vec<(IVec2, i32)> = tasks.meshing
+        .iter()
+        .filter(|(_, task)| task.is_finished())
+        .map(|(key, _)| *key)
+        .take(settings.max_uploads_per_frame)
+        .collect();
+
+    if finished.is_empty() {
+        return;
+    }
+
+    let entities: HashMap<IVec2, Entity> = chunk_query.iter().map(|(entity, chunk_entity)| (chunk_entity.position, entity)).collect();
+
+    for (chunk_position, section_y) in finished {
+        let section_meshes : SectionMeshes = block_on(tasks.meshing.remove(&(chunk_position, section_y)).unwrap());
+
+        let Some(entity) = entities.get(&chunk_position).copied() else { continue };
+
+        let opaque_mesh: Option<Handle<Mesh>> = section_meshes.opaque.map(|data| meshes.add(Mesh::from(data)));
+        let transparent_mesh: Option<Handle<Mesh>> = section_meshes.transparent.map(|data| meshes.add(Mesh::from(data)));
 
-fn create_cube_mesh(
-    meshes: &mut ResMut<Assets<Mesh>>,
-    chunk: &Chunk,
-    neighbors_by_direction: &mut HashMap<&'static str, &Chunk>,
-) -> Handle<Mesh> {
-    
-    let mut vertices: Vec<Vec3> = Vec::new();
-    let mut indices: Vec<u32> = Vec::new();
-    let mut vfaces: Vec<usize> = Vec::new();
-    let mut colors: Vec<Vec4> = Vec::new();
-    let mut normals: Vec<Vec3> = Vec::new();
+        let mut spawned: Vec<Entity> = Vec::new();
+        commands.entity(entity).with_children(|chunk_entity| {
+            spawned = spawn_section_meshes(chunk_entity, section_y, opaque_mesh, transparent_mesh, &chunk_materials);
+        });
 
-    let num_voxels: i32 = CHUNK_WIDTH * CHUNK_WIDTH * CHUNK_HEIGHT;
-    let num_voxel_per_row : i32 = CHUNK_WIDTH * CHUNK_WIDTH;
+        // The old meshes get removed from the assets as soon as their entities are gone
+        for old in section_entities.entities.insert((chunk_position, section_y), spawned).into_iter().flatten() {
+            commands.entity(old).despawn_recursive();
+        }
+    }
+}
 
-    
-    // Check for neighboring voxels, to hide faces
-    for i in 0..num_voxels {
-        let x: i32 = i % CHUNK_WIDTH;
-        let z: i32 = (i % (CHUNK_WIDTH * CHUNK_WIDTH)) / CHUNK_WIDTH;
-        let y: i32 = i / (CHUNK_WIDTH * CHUNK_WIDTH);
+impl Plugin for WorldPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.settings.clone())
+            .insert_resource(BiomeMap::new(&self.settings))
+            .insert_resource(WorldGenerator(self.settings.generator.build_or_fallback(&self.settings).into()))
+            .init_resource::<BlockRegistry>()
+            .init_resource::<PendingWrites>()
+            .insert_resource(VoxelWorld::new(&self.settings))
+            .init_resource::<ChunkTasks>()
+            .init_resource::<SectionEntities>()
+            .init_asset::<BlockDefinitions>()
+            .init_asset_loader::<BlockDefinitionsLoader>()
+            .add_plugins(MaterialPlugin::<VoxelMaterial>::default())
+            .add_event::<BlockDefinitionsChanged>()
+            .add_systems(Startup, (setup_chunk_materials, block_asset::load_block_definitions))
+            .add_systems(Update, (
+                stream_chunks,
+                insert_generated_sections,
+                block_asset::update_block_registry,
+                mark_changed_blocks_dirty,
+                queue_dirty_sections,
+                upload_section_meshes,
+            ).chain());
+    }
+}
 
-        if chunk.blocks[i as usize].block_type == BLOCK_AIR { continue; }
 
-        // X Direction------------------------------
-        if (i + 1 < num_voxels && (i + 1) % CHUNK_WIDTH != 0) && chunk.blocks[(i + 1) as usize].block_type == BLOCK_AIR {
-            vfaces.push(0);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::tasks::TaskPool;
+
+    // Tiny world so the tests stay fast
+    fn test_settings(seed: u32) -> WorldGenSettings {
+        WorldGenSettings {
+            seed,
+            chunk_width: 16,
+            vertical_render_distance: 1,
+            ground_level: 48,
+            amplitude: 16,
+            sea_level: 40,
+            render_distance: 2,
+            ..default()
         }
+    }
 
-        // X Direction right chunk neighbor if necessary
-        else if (i + 1) % CHUNK_WIDTH == 0 && neighbors_by_direction.contains_key("right") {
+    // Section y of the sections the tests generate, one below and one above the one with the ground level
+    fn test_sections(settings: &WorldGenSettings) -> std::ops::RangeInclusive<i32> {
+        let ground_section : i32 = settings.ground_level.div_euclid(SECTION_HEIGHT);
 
-            if neighbors_by_direction.get("right").unwrap().blocks[(i - CHUNK_WIDTH + 1) as usize].block_type == BLOCK_AIR{
+        ground_section - settings.vertical_render_distance..=ground_section + settings.vertical_render_distance
+    }
 
-                vfaces.push(0);
+    // Empty world with everything needed to generate sections into it, the generator is the one of the settings
+    struct TestWorld {
+        world: VoxelWorld,
+        pending: PendingWrites,
+        generator: Box<dyn TerrainGenerator>,
+        biomes: BiomeMap,
+        registry: BlockRegistry,
+        settings: WorldGenSettings,
+    }
+
+    impl TestWorld {
+        fn generate(&mut self, position: IVec3) {
+            generate_section(position, &mut self.world, &mut self.pending, self.generator.as_ref(), &self.biomes, &self.registry, &self.settings);
+        }
+
+        // Generate the test sections of the column at the position
+        fn generate_column(&mut self, position: IVec2) {
+            for section_y in test_sections(&self.settings) {
+                self.generate(IVec3::new(position.x, section_y * SECTION_HEIGHT, position.y));
             }
         }
-        
-        // -X Direction------------------------------
-        if (i > 0 && i % CHUNK_WIDTH != 0) && chunk.blocks[(i - 1) as usize].block_type == BLOCK_AIR{
-            vfaces.push(1);
+    }
+
+    fn test_world(settings: &WorldGenSettings) -> TestWorld {
+        TestWorld {
+            world: VoxelWorld::new(settings),
+            pending: PendingWrites::default(),
+            generator: settings.generator.build(settings).unwrap(),
+            biomes: BiomeMap::new(settings),
+            registry: BlockRegistry::default(),
+            settings: settings.clone(),
         }
+    }
 
-        // X Direction left chunk neighbor if necessary
-        else if (i % CHUNK_WIDTH == 0) && neighbors_by_direction.contains_key("left") {
+    // The classic flat world instead of the noise terrain
+    fn flat_settings(settings: &WorldGenSettings) -> WorldGenSettings {
+        WorldGenSettings { generator: GeneratorKind::Flat(FlatTerrain::classic()), ..settings.clone() }
+    }
+
+    // Generate the test sections of the column at the position on their own
+    fn generate_column(position: IVec2, settings: &WorldGenSettings) -> Chunk {
+        let mut test = test_world(settings);
+        test.generate_column(position);
+
+        test.world.chunks.remove(&position).unwrap()
+    }
+
+    // All blocks of the chunk from the lowest section up, so chunks can be compared
+    fn chunk_blocks(chunk: &Chunk) -> Vec<BlockId> {
+        chunk.sections.values().flat_map(|section| section.blocks.iter()).collect()
+    }
+
+    #[test]
+    fn same_seed_generates_same_chunk() {
+        let settings = test_settings(1234);
+        let position = IVec2::new(-8, 16);
+
+        let first = generate_column(position, &settings);
+        let second = generate_column(position, &settings);
+
+        assert_eq!(chunk_blocks(&first), chunk_blocks(&second));
+    }
+
+    // Generate the sections in the given order and return the blocks of every chunk
+    fn generate_region(positions: &[IVec3], settings: &WorldGenSettings) -> HashMap<IVec2, Vec<BlockId>> {
+        let mut test = test_world(settings);
+
+        for position in positions {
+            test.generate(*position);
+        }
+
+        test.world.chunks().map(|chunk| (chunk.position(), chunk_blocks(chunk))).collect()
+    }
 
-            if neighbors_by_direction.get("left").unwrap().blocks[(i + CHUNK_WIDTH - 1) as usize].block_type == BLOCK_AIR{
+    #[test]
+    fn decorations_dont_depend_on_generation_order() {
+        let settings = test_settings(7);
 
-                vfaces.push(1);
+        let mut positions: Vec<IVec3> = Vec::new();
+        for x in -1..=1 {
+            for z in -1..=1 {
+                for section_y in test_sections(&settings) {
+                    positions.push(IVec3::new(x * settings.chunk_width, section_y * SECTION_HEIGHT, z * settings.chunk_width));
+                }
             }
         }
-        
 
+        let forward = generate_region(&positions, &settings);
+
+        // Top sections first, so the crowns of the trees wait in the pending writes for the sections below them
+        positions.reverse();
+        let backward = generate_region(&positions, &settings);
+
+        // Outer chunks first, so the middle chunk gets the writes of all its neighbors from the pending writes
+        positions.sort_by_key(|position| -(position.x.abs() + position.z.abs()));
+        let outside_in = generate_region(&positions, &settings);
+
+        assert!(forward == backward);
+        assert!(forward == outside_in);
+
+        // Make sure the test world has decorations at all
+        let logs : usize = forward.values().flatten().filter(|block| **block == BLOCK_LOG).count();
+        assert!(logs > 0);
+    }
+
+    #[test]
+    fn sections_can_be_generated_far_above_and_below_the_ground() {
+        let settings = WorldGenSettings { bedrock_level: None, ..test_settings(3) };
+        let generator = NoiseTerrain::new(&settings);
+
+        let deep = generator.generate(IVec3::new(0, -1024, 0), IVec3::new(16, SECTION_HEIGHT, 16));
+        let high = generator.generate(IVec3::new(0, 1024, 0), IVec3::new(16, SECTION_HEIGHT, 16));
+
+        // Without a bedrock level the underground goes down forever
+        assert!(deep.contains(&BLOCK_STONE) && !deep.contains(&BLOCK_BEDROCK));
+        assert!(high.iter().all(|block| *block == BLOCK_AIR));
+    }
+
+    #[test]
+    fn missing_sections_are_generated_later() {
+        let mut test = test_world(&test_settings(5));
+
+        test.generate(IVec3::new(0, 0, 0));
+
+        let chunk = test.world.chunk_mut(IVec2::ZERO).unwrap();
+        assert!(chunk.get_block(IVec3::new(0, SECTION_HEIGHT, 0)).is_none());
+        assert!(!chunk.set_block(IVec3::new(0, -1, 0), BLOCK_STONE, &test.registry));
+
+        chunk.sections.get_mut(&0).unwrap().dirty = false;
+
+        test.generate(IVec3::new(0, -SECTION_HEIGHT, 0));
+
+        // The section above has to be meshed again, its bottom faces can be hidden now
+        let chunk = test.world.chunk(IVec2::ZERO).unwrap();
+        assert!(chunk.sections[&0].dirty);
+        assert_eq!(chunk.get_block(IVec3::new(0, -1, 0)), Some(BLOCK_BEDROCK));
+    }
+
+    #[test]
+    fn generated_chunk_is_smaller_than_two_ints_per_block() {
+        let settings = test_settings(1234);
+        let chunk = generate_column(IVec2::ZERO, &settings);
+
+        // The old storage had an i32 id and an i32 block type per block
+        let unpacked : usize = (chunk.size.x * chunk.size.y * chunk.size.z) as usize * chunk.sections.len() * 8;
+
+        let packed : usize = chunk.sections.values().map(|section| section.blocks.memory_usage()).sum();
+
+        assert!(packed * 8 <= unpacked);
+    }
+
+    #[test]
+    fn uniform_sections_need_almost_no_memory() {
+        let chunk = generate_column(IVec2::ZERO, &flat_settings(&test_settings(0)));
+        let registry = BlockRegistry::default();
+
+        // The classic flat world is only 4 blocks high, so the two sections above it are only air
+        assert!(!chunk.sections[&0].is_empty(&registry));
+        assert!(chunk.sections[&1].is_empty(&registry));
+        assert!(chunk.sections[&2].is_empty(&registry));
+        assert!(chunk.sections[&1].blocks.memory_usage() < 128);
+        assert_eq!(chunk.surface_height(0, 0), Some(4));
+    }
+
+    #[test]
+    fn editing_a_block_only_marks_its_sections_dirty() {
+        let mut chunk = generate_column(IVec2::ZERO, &flat_settings(&test_settings(0)));
+        let registry = BlockRegistry::default();
+
+        for section in chunk.sections.values_mut() {
+            section.dirty = false;
+        }
+
+        // Middle of the second section
+        assert!(chunk.set_block(IVec3::new(0, 48, 0), BLOCK_STONE, &registry));
+        assert!(chunk.sections.values().map(|section| section.dirty).eq([false, true, false]));
+        assert_eq!(chunk.get_block(IVec3::new(0, 48, 0)), Some(BLOCK_STONE));
+
+        for section in chunk.sections.values_mut() {
+            section.dirty = false;
+        }
+
+        // Top layer of the first section, which hides the bottom face of the block above
+        chunk.set_block(IVec3::new(0, 31, 0), BLOCK_STONE, &registry);
+        assert!(chunk.sections.values().map(|section| section.dirty).eq([true, true, false]));
+    }
+
+    #[test]
+    fn editing_blocks_keeps_the_heightmap_up_to_date() {
+        let mut test = test_world(&flat_settings(&test_settings(0)));
+        test.generate_column(IVec2::ZERO);
+
+        let TestWorld { mut world, registry, .. } = test;
+        let surface = |world: &VoxelWorld, x: i32, z: i32| world.chunk(IVec2::ZERO).unwrap().surface_height(x, z);
+
+        // A block floating in the section above the grass raises the column, removing the grass below it doesn't change it
+        assert!(world.set_block(IVec3::new(3, 40, 5), BLOCK_STONE, &registry));
+        assert_eq!(surface(&world, 3, 5), Some(41));
+        assert!(world.set_block(IVec3::new(3, 3, 5), BLOCK_AIR, &registry));
+        assert_eq!(surface(&world, 3, 5), Some(41));
+
+        // Without the floating block the dirt below the removed grass is the top
+        assert!(world.set_block(IVec3::new(3, 40, 5), BLOCK_AIR, &registry));
+        assert_eq!(surface(&world, 3, 5), Some(3));
+
+        // Blocks which aren't solid don't count
+        assert!(world.set_block(IVec3::new(3, 10, 5), BLOCK_WATER, &registry));
+        assert_eq!(surface(&world, 3, 5), Some(3));
 
-        // Y Direction ------------------------------
-        // (not necessary to check for neighbor because no chunk is on top of each other)
-        if ((i + num_voxel_per_row < num_voxels) && chunk.blocks[(i + num_voxel_per_row) as usize].block_type == BLOCK_AIR)
-        || (i + num_voxel_per_row >= num_voxels) {
-            vfaces.push(2);
+        for y in 0..3 {
+            assert!(world.set_block(IVec3::new(3, y, 5), BLOCK_AIR, &registry));
         }
-        
-        // -Y Direction ------------------------------
-        if (i - num_voxel_per_row >= 0) && chunk.blocks[(i - num_voxel_per_row) as usize].block_type == BLOCK_AIR {
-            vfaces.push(3);
+
+        assert_eq!(surface(&world, 3, 5), None);
+        assert_eq!(surface(&world, 4, 5), Some(4));
+    }
+
+    #[test]
+    fn different_seeds_generate_different_chunks() {
+        let position = IVec2::new(0, 0);
+
+        let (first_settings, second_settings) = (test_settings(1), test_settings(2));
+
+        let first = generate_column(position, &first_settings);
+        let second = generate_column(position, &second_settings);
+
+        assert_ne!(chunk_blocks(&first), chunk_blocks(&second));
+    }
+
+    #[test]
+    fn world_coordinates_round_down_into_chunks() {
+        let world = VoxelWorld::new(&test_settings(0));
+
+        assert_eq!(world.chunk_position(IVec3::new(0, 5, 15)), IVec2::new(0, 0));
+        assert_eq!(world.chunk_position(IVec3::new(16, -5, -1)), IVec2::new(16, -16));
+        assert_eq!(world.chunk_position(IVec3::new(-16, 0, -17)), IVec2::new(-16, -32));
+
+        assert_eq!(world.local_position(IVec3::new(-1, -40, -16)), IVec3::new(15, -40, 0));
+        assert_eq!(world.local_position(IVec3::new(17, 3, -17)), IVec3::new(1, 3, 15));
+
+        for position in [IVec3::new(-1, -40, -16), IVec3::new(17, 3, -17), IVec3::new(-33, 100, 64)] {
+            assert_eq!(world.world_position(world.chunk_position(position), world.local_position(position)), position);
+        }
+    }
+
+    #[test]
+    fn set_block_marks_neighbor_chunks_dirty() {
+        let mut world = flat_world(&[IVec2::new(-16, 0), IVec2::ZERO], &test_settings(0));
+        let registry = BlockRegistry::default();
+
+        for chunk in world.chunks_mut() {
+            chunk.sections.get_mut(&0).unwrap().dirty = false;
         }
 
-        
+        // Last column of the chunk at -16, next to the chunk at 0
+        assert!(world.set_block(IVec3::new(-1, 10, 3), BLOCK_STONE, &registry));
+        assert_eq!(world.get_block(IVec3::new(-1, 10, 3)), Some(BLOCK_STONE));
+        assert_eq!(world.chunk(IVec2::new(-16, 0)).unwrap().get_block(IVec3::new(15, 10, 3)), Some(BLOCK_STONE));
+        assert!(world.chunks().all(|chunk| chunk.sections[&0].dirty));
+
+        // Nothing is loaded there
+        assert!(!world.set_block(IVec3::new(40, 10, 3), BLOCK_STONE, &registry));
+        assert_eq!(world.get_block(IVec3::new(0, SECTION_HEIGHT, 0)), None);
+    }
+
+    #[test]
+    fn changed_block_definitions_mark_neighbor_chunks_dirty() {
+        let settings = test_settings(0);
+        let width : i32 = settings.chunk_width;
+        let mut world = flat_world(&[IVec2::ZERO, IVec2::new(-width, 0), IVec2::new(-width, -width), IVec2::new(2 * width, 0)], &settings);
+        let registry = BlockRegistry::default();
 
+        // A log on the west border of the middle chunk, the only one in the world
+        assert!(world.set_block(IVec3::new(0, 4, 0), BLOCK_LOG, &registry));
 
-        // Z Direction------------------------------
-        if (i + CHUNK_WIDTH < num_voxels && i / num_voxel_per_row == (i + CHUNK_WIDTH) / num_voxel_per_row) && chunk.blocks[(i + CHUNK_WIDTH) as usize].block_type == BLOCK_AIR {
-            vfaces.push(4);
+        for chunk in world.chunks_mut() {
+            chunk.sections.get_mut(&0).unwrap().dirty = false;
         }
-   
-        // Z Direction down chunk neighbor if necessary
-        else if (i / num_voxel_per_row != (i + CHUNK_WIDTH) / num_voxel_per_row) && neighbors_by_direction.contains_key("down") {
 
-            if neighbors_by_direction.get("down").unwrap().blocks[(i - CHUNK_WIDTH * (CHUNK_WIDTH - 1)) as usize].block_type == BLOCK_AIR{
+        let mut app = App::new();
+        app.insert_resource(world)
+            .add_event::<BlockDefinitionsChanged>()
+            .add_systems(Update, mark_changed_blocks_dirty);
+        app.world.send_event(BlockDefinitionsChanged { blocks: vec![BLOCK_LOG] });
+        app.update();
+
+        // The west and the north west chunk touch the section with the log, the chunk two steps east doesn't
+        let world = app.world.resource::<VoxelWorld>();
+        let dirty = |x: i32, z: i32| world.chunk(IVec2::new(x, z) * width).unwrap().sections[&0].dirty;
+
+        assert!(dirty(0, 0));
+        assert!(dirty(-1, 0));
+        assert!(dirty(-1, -1));
+        assert!(!dirty(2, 0));
+    }
 
-                vfaces.push(4);
-            }
+    // Classic flat world with the lowest section of every chunk at the positions
+    fn flat_world(positions: &[IVec2], settings: &WorldGenSettings) -> VoxelWorld {
+        let mut test = test_world(&flat_settings(settings));
+
+        for position in positions {
+            test.generate(IVec3::new(position.x, 0, position.y));
         }
 
+        test.world
+    }
+
+    // Mesh the chunk and return the vertices of the faces on its border in the direction
+    fn border_faces(world: &VoxelWorld, position: IVec2, direction: Direction, settings: &WorldGenSettings) -> Vec<Vec3> {
+        let registry = BlockRegistry::default();
+        let chunk = world.chunk(position).unwrap();
+
+        let data = mesh_chunk(chunk, &find_neighbors(chunk, world), &registry, MeshPass::Opaque, settings.mesher);
+
+        // Faces on the border are half a block outside of the first or last block
+        let offset = direction.offset().as_vec3();
+        let border : f32 = if offset.max_element() > 0.0 { settings.chunk_width as f32 - 0.5 } else { -0.5 };
 
-        // -Z Direction------------------------------
-        if (i - CHUNK_WIDTH >= 0 && i / num_voxel_per_row == (i - CHUNK_WIDTH) / num_voxel_per_row) && chunk.blocks[(i - CHUNK_WIDTH) as usize].block_type == BLOCK_AIR {
-            vfaces.push(5);
+        data.positions
+            .iter()
+            .zip(&data.normals)
+            .map(|(position, normal)| (Vec3::from(*position), Vec3::from(*normal)))
+            .filter(|(position, normal)| *normal == offset && position.dot(offset.abs()) == border)
+            .map(|(position, _)| position)
+            .collect()
+    }
+
+    #[test]
+    fn faces_between_chunks_are_culled_on_all_sides() {
+        let settings = test_settings(0);
+        let positions : Vec<IVec2> = [IVec2::ZERO].into_iter()
+            .chain(HORIZONTAL_DIRECTIONS.iter().map(|direction| direction.chunk_offset() * settings.chunk_width))
+            .collect();
+        let mut world = flat_world(&positions, &settings);
+        let registry = BlockRegistry::default();
+
+        for direction in HORIZONTAL_DIRECTIONS {
+            assert!(border_faces(&world, IVec2::ZERO, direction, &settings).is_empty(), "{:?}", direction);
         }
 
-        // Z Direction top chunk neighbor if necessary
-        else if (i / num_voxel_per_row != (i - CHUNK_WIDTH) / num_voxel_per_row) && neighbors_by_direction.contains_key("top") {
+        // Dig a hole into every neighbor, right next to a block of the middle chunk
+        for direction in HORIZONTAL_DIRECTIONS {
+            let block = IVec3::new(5, 2, 9);
+            let border = block * (IVec3::ONE - direction.offset().abs()) + direction.offset().max(IVec3::ZERO) * (settings.chunk_width - 1);
 
-            if neighbors_by_direction.get("top").unwrap().blocks[(i + CHUNK_WIDTH * (CHUNK_WIDTH - 1)) as usize].block_type == BLOCK_AIR{
+            assert!(world.set_block(border + direction.offset(), BLOCK_AIR, &registry));
 
-                vfaces.push(5);
-            }
+            let faces = border_faces(&world, IVec2::ZERO, direction, &settings);
+            assert_eq!(faces.len(), 4, "{:?}", direction);
+
+            // The face is on the side of the block next to the hole
+            let center : Vec3 = faces.iter().sum::<Vec3>() / 4.0;
+            assert_eq!(center, border.as_vec3() + direction.offset().as_vec3() * 0.5, "{:?}", direction);
         }
+    }
+
+    #[test]
+    fn chunks_further_away_are_not_neighbors() {
+        let settings = test_settings(0);
+        let width : i32 = settings.chunk_width;
+        let mut world = flat_world(&[IVec2::ZERO, IVec2::new(0, 2 * width), IVec2::new(-2 * width, 0)], &settings);
+        let registry = BlockRegistry::default();
 
+        // Holes in the chunks two steps away, which would show up as faces if they were taken as neighbors
+        assert!(world.set_block(IVec3::new(5, 2, 2 * width), BLOCK_AIR, &registry));
+        assert!(world.set_block(IVec3::new(-width - 1, 2, 5), BLOCK_AIR, &registry));
 
+        let chunk = world.chunk(IVec2::ZERO).unwrap();
+        assert!(find_neighbors(chunk, &world).is_empty());
 
+        // Without a neighbor the border faces stay hidden until the neighbor gets loaded
+        for direction in HORIZONTAL_DIRECTIONS {
+            assert!(border_faces(&world, IVec2::ZERO, direction, &settings).is_empty(), "{:?}", direction);
+        }
+    }
 
-        // Generate geometry data of 1 voxel which is part of the chunk 
-        generate_cube(&mut vertices, &mut vfaces, &mut normals, 
-            x as usize, y as usize, z as usize);
-
-        vfaces.clear();
-    }
-
-    // Optimized mesh algorithm -----------------
-
-    // Sort normals and vertices by normals so you can iterate through each face direction group (first all up faces --> down faces... )
-    let mut combined: Vec<(&Vec3, &Vec3)> = normals.iter().zip(vertices.iter()).collect();
-    combined.sort_by(|a, b| partial_cmp(a.0,b.0).unwrap());
-
-    let mut sorted_normals: Vec<Vec3> = Vec::new();
-    let mut sorted_vertices: Vec<Vec3> = Vec::new();
-
-    for (normal, vertex) in combined {
-        sorted_normals.push(normal.clone());
-        sorted_vertices.push(vertex.clone());
-    }
-
-    // Go through all sorted vertices and normals and check which faces are neighbors and can be merged
-    //
-    // Can be merged:
-    //
-    //   1     4==5      7
-    //   +-------+-------+
-    //   |       |       |
-    //   |       |       |
-    //   |       |       |
-    //   +-------+-------+ 
-    //   2     3==6      8
-    //
-    // Cant be merged:
-    //
-    //   1     4==5      7
-    //   +-------+-------+
-    //   |       |       |
-    //   |       |       |
-    //   |       |       |
-    //   +-------+       | 
-    //   2      4|       |   
-    //           |       |
-    //           |       |
-    //           +-------+ 
-    //   4!=5    5       8
-
-    // Do the optimization as long as something gets optimized 
-    // TO-DO: Maybe some limit is better so only max face width / height is X
-    let mut g = 1;
-    while g != 0{
-        g = 0;
-        let mut i = 0;
-        let mut j: usize = 0;
-        // Go through the verticies 
-        while i < sorted_vertices.len() {
-
-            if i % 4 != 0 {                
-                
-                i = i + 1;
-                continue;
+    #[test]
+    fn merged_mesh_covers_the_same_faces_as_single_faces() {
+        let settings = test_settings(42);
+        let mut test = test_world(&settings);
+
+        for x in -1..=1 {
+            for z in -1..=1 {
+                test.generate_column(IVec2::new(x, z) * settings.chunk_width);
             }
-                let first_number = sorted_vertices[i];
-                let fourth_number = sorted_vertices[i+3];
-            // Find pairs of mergeable faces 
-            while j < sorted_vertices.len() {
+        }
 
-                // Only go through the face if it is the "starting" vertex"
-                if j % 4 != 0 {
+        let TestWorld { world, registry, .. } = test;
 
-                    j = j + 1;
-                    continue;
-                }
-                // Skip comparing the same subarray
-                    if i != j { 
-                        
-                        // Check if mergeable
-                        if sorted_vertices.len() > j + 2 && first_number == sorted_vertices[j+1] && fourth_number == sorted_vertices[j+2] &&  sorted_normals[j+2] == sorted_normals[i] && sorted_normals[i+3] == sorted_normals[j+1] {
-                            
-                            if i < j{
-                                // Combine the faces via removing and adding vertices together
-                                sorted_vertices.remove_multiple(vec![i,i+3,j+1,j+2]);
-                                let copy = sorted_vertices.remove(j-2);
-                                let copy2 = sorted_vertices.remove(j-2);
-                                sorted_vertices.insert(i, copy);
-                                sorted_vertices.insert(i+3, copy2);
-
-                                sorted_normals.remove_multiple(vec![i,i+3,j+1,j+2]);
-                                let copy = sorted_normals.remove(j-2);
-                                let copy2 = sorted_normals.remove(j-2);
-                                sorted_normals.insert(i, copy);
-                                sorted_normals.insert(i+3, copy2);
-
-                                // Optimized a mesh g++
-                                g = g + 1;
-                            }else{
-                                sorted_vertices.remove_multiple(vec![i,i+3,j+1,j+2]);
-                                let copy = sorted_vertices.remove(i-2);
-                                let copy2 = sorted_vertices.remove(i-2);
-                                sorted_vertices.insert(j+1, copy);
-                                sorted_vertices.insert(j+2, copy2);
-                                
-                                sorted_normals.remove_multiple(vec![i,i+3,j+1,j+2]);
-                                let copy = sorted_normals.remove(i-2);
-                                let copy2 = sorted_normals.remove(i-2);
-                                sorted_normals.insert(j+1, copy);
-                                sorted_normals.insert(j+2, copy2);
-                                
-                                // Optimized a mesh g++
-                                g = g + 1;
-                            }
+        let size = IVec3::new(settings.chunk_width, SECTION_HEIGHT, settings.chunk_width);
+        let chunk = world.chunk(IVec2::ZERO).unwrap();
+        let neighbor_chunks = find_neighbors(chunk, &world);
+        let mut quads : usize = 0;
+        let mut faces : usize = 0;
+
+        for section_y in test_sections(&settings) {
+            let neighbor_sections = find_neighbor_sections(chunk, section_y, &neighbor_chunks);
+
+            for pass in [MeshPass::Opaque, MeshPass::Transparent] {
+                // Every visible face of every block on its own, like the mesh looked before merging
+                let mut expected: HashMap<(IVec3, IVec3), ([Vec4; 4], u32)> = HashMap::new();
+
+                for i in 0..size.x * size.y * size.z {
+                    let position = IVec3::new(i % size.x, i / (size.x * size.z), (i / size.x) % size.z);
+
+                    for (face, direction) in DIRECTIONS.into_iter().enumerate() {
+                        if let Some(block) = mesher::visible_face(&chunk.sections[&section_y], &neighbor_sections, &registry, pass, position, direction, size) {
+                            let ao = mesher::face_ao(&chunk.sections[&section_y], &neighbor_sections, &registry, position, face, size);
+                            let definition = registry.get(block);
+                            let colors = ao.map(|ao| mesher::shade(mesher::vertex_color(definition), ao));
+                            let tile = definition.texture.map_or(NO_TEXTURE, |texture| texture.tile(direction));
+                            expected.insert((direction.offset(), position), (colors, tile));
                         }
                     }
-                // Check next pair 
-                j = j + 1;
-            }
-            j = 0;
-            // Check next face for merge
-            i = i + 1;
-        }
-    }
-    
-    // Same Code but different rule for neighboring because of different direction
-    let mut g = 1;
-    while g != 0{
-        g = 0;
-        let mut i = 0;
-        let mut j: usize = 0;  
-        while i < sorted_vertices.len() {
-            if i % 4 != 0 {
-                i = i + 1;
-                continue;
-            }
-                let third_number = sorted_vertices[i+2];
-                let fourth_number = sorted_vertices[i+3];
-    
-            while j < sorted_vertices.len() {
-                if j % 4 != 0 {
-                    j = j + 1;
-                    continue;
                 }
-                    if i != j {
-                        
-                        //different vertices checked / removed / added
-                        if sorted_vertices.len() > j + 2 && third_number == sorted_vertices[j+1] && fourth_number == sorted_vertices[j] &&  sorted_normals[j+3] == sorted_normals[i] && sorted_normals[i+2] == sorted_normals[j+1] {
-                            
-                            if i < j{
-
-                                sorted_vertices.remove_multiple(vec![i+2,i+3,j,j+1]);
-                                let copy = sorted_vertices.remove(j+2-4);
-                                let copy2 = sorted_vertices.remove(j+2-4);
-                                sorted_vertices.insert(i+2, copy);
-                                sorted_vertices.insert(i+3, copy2);
-
-                                sorted_normals.remove_multiple(vec![i+2,i+3,j+2,j+3]);
-                                let copy = sorted_normals.remove(j+2-4);
-                                let copy2 = sorted_normals.remove(j+2-4);
-                                sorted_normals.insert(i+2, copy);
-                                sorted_normals.insert(i+3, copy2);
-
-                                g = g + 1;
-                            }else{
-
-                                sorted_vertices.remove_multiple(vec![i+2,i+3,j,j+1]);
-                                let copy = sorted_vertices.remove(i-2);
-                                let copy2 = sorted_vertices.remove(i-2);
-                                sorted_vertices.insert(j, copy);
-                                sorted_vertices.insert(j+1, copy2);
-
-                                sorted_normals.remove_multiple(vec![i+2,i+3,j,j+1]);
-                                let copy = sorted_normals.remove(i-2);
-                                let copy2 = sorted_normals.remove(i-2);
-                                sorted_normals.insert(j, copy);
-                                sorted_normals.insert(j+1, copy2);
-
-                                g = g + 1;
-                            }
-                        }
-                    }
-                j = j + 1;
+
+                let data = mesher::mesh_section(&chunk.sections[&section_y], &neighbor_sections, &registry, size, pass, MesherKind::PerVoxel);
+                quads += data.positions.len() / 4;
+                faces += expected.len();
+
+                // Each face is covered by exactly one rectangle with the same corner colors and tile, and the rectangles cover nothing else
+                for (normal, position, colors, tile) in mesher::single_faces(&data) {
+                    assert_eq!(expected.remove(&(normal, position)), Some((colors, tile)), "{:?} {:?}", normal, position);
+                }
+
+                assert!(expected.is_empty(), "{} faces are missing", expected.len());
             }
-            j = 0;
-            i = i + 1;
+        }
+
+        // Rough terrain has lots of shaded corners which can't be merged, but still far less rectangles than faces
+        assert!(quads * 4 < faces * 3, "{} rectangles for {} faces", quads, faces);
+    }
+
+    // Normal, lowest and highest corner at twice the size, color bits and tile of a rectangle
+    type Rectangle = ([i32; 3], [i32; 3], [i32; 3], [u32; 4], u32);
+
+    // Every rectangle of the mesh, sorted so meshes can be compared
+    fn rectangles(data: &ChunkMeshData) -> Vec<Rectangle> {
+        let mut rectangles: Vec<Rectangle> = data.positions
+            .chunks(4)
+            .zip(data.normals.chunks(4).zip(data.colors.chunks(4)).zip(data.tiles.chunks(4)))
+            .map(|(corners, ((normals, colors), tiles))| {
+                let (min, max) = mesher::quad_bounds(corners);
+
+                (
+                    Vec3::from(normals[0]).as_ivec3().to_array(),
+                    (min * 2.0).as_ivec3().to_array(),
+                    (max * 2.0).as_ivec3().to_array(),
+                    colors[0].map(f32::to_bits),
+                    tiles[0],
+                )
+            })
+            .collect();
+
+        rectangles.sort();
+        rectangles
+    }
+
+    #[test]
+    fn bitmask_mesher_builds_the_same_rectangles() {
+        // 32 blocks wide, so the rows fill all bits, and density terrain for caves and overhangs
+        let settings = WorldGenSettings { chunk_width: 32, terrain_mode: TerrainMode::Density, ..test_settings(11) };
+        let mut test = test_world(&settings);
+
+        for position in [IVec2::ZERO, IVec2::new(settings.chunk_width, 0), IVec2::new(0, -settings.chunk_width)] {
+            test.generate_column(position);
+        }
+
+        let TestWorld { world, registry, .. } = test;
+
+        // Some neighbors are loaded and some aren't, both have to be handled the same way
+        let chunk = world.chunk(IVec2::ZERO).unwrap();
+        let neighbors = find_neighbors(chunk, &world);
+
+        for pass in [MeshPass::Opaque, MeshPass::Transparent] {
+            let per_voxel = mesh_chunk(chunk, &neighbors, &registry, pass, MesherKind::PerVoxel);
+            let bitmask = mesh_chunk(chunk, &neighbors, &registry, pass, MesherKind::Bitmask);
+
+            assert!(!per_voxel.is_empty(), "{:?}", pass);
+            assert_eq!(rectangles(&per_voxel), rectangles(&bitmask), "{:?}", pass);
         }
     }
 
-    // Generate all indices and colors for a face
+    #[test]
+    fn flat_ground_is_one_rectangle() {
+        let settings = test_settings(0);
+        let positions : Vec<IVec2> = [IVec2::ZERO].into_iter()
+            .chain(HORIZONTAL_DIRECTIONS.iter().map(|direction| direction.chunk_offset() * settings.chunk_width))
+            .collect();
+        let mut world = flat_world(&positions, &settings);
+        let registry = BlockRegistry::default();
+
+        let mesh_middle = |world: &VoxelWorld| {
+            let chunk = world.chunk(IVec2::ZERO).unwrap();
+
+            mesh_chunk(chunk, &find_neighbors(chunk, world), &registry, MeshPass::Opaque, settings.mesher)
+        };
+
+        // The sides are hidden by the neighbors and there is nothing below, only the grass on top is left
+        let data = mesh_middle(&world);
+        assert_eq!(data.positions.len(), 4);
+        assert_eq!(mesher::single_faces(&data).len(), (settings.chunk_width * settings.chunk_width) as usize);
+
+        // A different block in the middle of the grass splits it into a few rectangles around it
+        assert!(world.set_block(IVec3::new(5, 3, 9), BLOCK_STONE, &registry));
 
-    for i in 0..sorted_vertices.len()/4{
-        indices.append(&mut vec![i as u32 *4 + 0 as u32, i as u32 *4 + 1 as u32, i as u32 *4 + 2 as u32, i as u32 *4 + 2 as u32, i as u32 *4 + 3 as u32, i as u32 *4 + 0]);
-        let random_hue: f32 = rand::thread_rng().gen_range(0.0..=1.0);
-        let color : Vec4 = Vec4::new(0.0,0.0,random_hue,1.0);
-        colors.push(color);
-        colors.push(color);
-        colors.push(color);
-        colors.push(color);
+        let data = mesh_middle(&world);
+        assert!(data.positions.len() / 4 <= 5, "{} rectangles", data.positions.len() / 4);
+        assert_eq!(mesher::single_faces(&data).len(), (settings.chunk_width * settings.chunk_width) as usize);
     }
 
+    #[test]
+    fn ambient_occlusion_darkens_corners_next_to_solid_blocks() {
+        let settings = test_settings(0);
+        let width : i32 = settings.chunk_width;
+        let positions : Vec<IVec2> = (-1..=1).flat_map(|x| (-1..=1).map(move |z| IVec2::new(x, z) * width)).collect();
+        let mut world = flat_world(&positions, &settings);
+        let registry = BlockRegistry::default();
+        let size = IVec3::new(width, SECTION_HEIGHT, width);
+
+        for chunk in world.chunks_mut() {
+            chunk.sections.get_mut(&0).unwrap().dirty = false;
+        }
 
-    // Generate chunk-mesh with the vertices and indices provided by the voxel data
-    meshes.add(Mesh::new(PrimitiveTopology::TriangleList)
-        .with_inserted_attribute( Mesh::ATTRIBUTE_POSITION, sorted_vertices)
-        .with_inserted_attribute( Mesh::ATTRIBUTE_NORMAL, sorted_normals)
-        .with_inserted_attribute( Mesh::ATTRIBUTE_COLOR, colors)
-        .with_indices(Some(Indices::U32(indices)))
-    )
-}
+        // Blocks on top of the grass, which is the layer at y 3
+        let blocks = [
+            IVec3::new(5, 4, 9),
+            // Next to the middle chunk in the west chunk and in the north west chunk
+            IVec3::new(-1, 4, 5),
+            IVec3::new(-1, 4, -1),
+            // On two sides of the same corner
+            IVec3::new(9, 4, 3),
+            IVec3::new(10, 4, 2),
+        ];
+
+        for block in blocks {
+            assert!(world.set_block(block, BLOCK_STONE, &registry));
+        }
 
-// Generate one voxel with visible faces of the chunk-mesh
-fn generate_cube(
-    vertices: &mut Vec<Vec3>,
-    vfaces: &mut Vec<usize>,
-    normals: &mut Vec<Vec3>,
-    x: usize,
-    y: usize,
-    z: usize,
-) {
+        // The diagonal chunk can darken corners of the middle chunk, so it has to be meshed again as well
+        assert!(world.chunk(IVec2::ZERO).unwrap().sections[&0].dirty);
+
+        let chunk = world.chunk(IVec2::ZERO).unwrap();
+        let neighbor_sections = find_neighbor_sections(chunk, 0, &find_neighbors(chunk, &world));
+        let top_ao = |x: i32, z: i32| mesher::face_ao(&chunk.sections[&0], &neighbor_sections, &registry, IVec3::new(x, 3, z), 2, size);
+
+        // The corners of the top face are at +x -z, -x -z, -x +z and +x +z
+        assert_eq!(top_ao(0, 12), [3, 3, 3, 3]);
+        assert_eq!(top_ao(6, 9), [3, 2, 2, 3]);
+        assert_eq!(top_ao(6, 10), [3, 2, 3, 3]);
+        assert_eq!(top_ao(0, 5), [3, 2, 2, 3]);
+        assert_eq!(top_ao(0, 0), [3, 2, 3, 3]);
+        assert_eq!(top_ao(10, 3), [2, 0, 2, 3]);
+
+        // Faces only get merged with faces which have the same shading
+        let data = mesh_chunk(chunk, &find_neighbors(chunk, &world), &registry, MeshPass::Opaque, settings.mesher);
+        let quad : usize = data.positions
+            .chunks(4)
+            .position(|corners| {
+                let (min, max) = mesher::quad_bounds(corners);
+                min == Vec3::new(9.5, 3.5, 2.5) && max == Vec3::new(10.5, 3.5, 3.5)
+            })
+            .expect("the darkened face is a rectangle of its own");
+
+        // The face is split along the diagonal through its darkest corner
+        let first : u32 = quad as u32 * 4;
+        assert_eq!(data.indices[quad * 6..quad * 6 + 6], [1, 2, 3, 3, 0, 1].map(|index| first + index));
+        assert_eq!(Vec4::from(data.colors[quad * 4 + 1]), mesher::shade(mesher::vertex_color(registry.get(BLOCK_GRASS)), 0));
+    }
 
-    // Array for all possible vertex position of a Voxel
-    let positions: [[Vec3; 4];6]  = 
-    [[
-         // X Direction Position
-        Vec3::new(0.5, -0.5, -0.5),
-        Vec3::new(0.5, 0.5, -0.5),             
-        Vec3::new(0.5, 0.5, 0.5),             
-        Vec3::new(0.5, -0.5, 0.5), 
-        ],[
-        Vec3::new(-0.5, 0.5, 0.5),
-        Vec3::new(-0.5, 0.5, -0.5),
-        Vec3::new(-0.5, -0.5, -0.5), 
-        Vec3::new(-0.5, -0.5, 0.5),
-        ],[
-        // Y Direction Position
-        Vec3::new(0.5, 0.5, -0.5),
-        Vec3::new(-0.5, 0.5, -0.5), 
-        Vec3::new(-0.5, 0.5, 0.5),
-        Vec3::new(0.5, 0.5, 0.5),
-        ],[
-        Vec3::new(-0.5, -0.5, -0.5), 
-        Vec3::new(0.5, -0.5, -0.5),  
-        Vec3::new(0.5, -0.5, 0.5),
-        Vec3::new(-0.5, -0.5, 0.5),
-        ],[
-        // Z Direction Position
-        Vec3::new(-0.5, -0.5, 0.5),
-        Vec3::new(0.5, -0.5, 0.5),
-        Vec3::new(0.5, 0.5, 0.5),
-        Vec3::new(-0.5, 0.5, 0.5), 
-        ],[
-        Vec3::new(-0.5, 0.5, -0.5),
-        Vec3::new(0.5, 0.5, -0.5),
-        Vec3::new(0.5, -0.5, -0.5), 
-        Vec3::new(-0.5, -0.5, -0.5),
-    ]];
-
-    // Array for all possible normal direction of a Voxel 
-    // TODO: Simplify normal data 
-    let normal: [[Vec3; 4];6]  = 
-    [[
-        // X Direction Normals
-        Vec3::new( 1.0 , 0.0 , 0.0),
-        Vec3::new( 1.0 , 0.0 , 0.0),       
-        Vec3::new( 1.0 , 0.0 , 0.0),            
-        Vec3::new( 1.0, 0.0 , 0.0 ),
-        ],[
-        Vec3::new( -1.0 , 0.0 , 0.0),
-        Vec3::new( -1.0 , 0.0 , 0.0),       
-        Vec3::new( -1.0 , 0.0 , 0.0),            
-        Vec3::new( -1.0, 0.0 , 0.0 ),
-        ],[
-        // Y Direction Normals
-        Vec3::new( 0.0 , 1.0 , 0.0),
-        Vec3::new( 0.0, 1.0 , 0.0),       
-        Vec3::new( 0.0, 1.0 , 0.0),            
-        Vec3::new( 0.0, 1.0 , 0.0 ),
-        ],[
-        Vec3::new( 0.0 , -1.0 , 0.0),
-        Vec3::new( 0.0 , -1.0  , 0.0),       
-        Vec3::new( 0.0 , -1.0  , 0.0),            
-        Vec3::new( 0.0, -1.0  , 0.0 ),
-        ],[
-        // Z Direction Normals
-        Vec3::new( 0.0 , 0.0 , 1.0),
-        Vec3::new( 0.0 , 0.0 , 1.0),       
-        Vec3::new( 0.0 , 0.0 , 1.0),            
-        Vec3::new( 0.0 , 0.0 , 1.0),
-        ],[
-        Vec3::new( 0.0 , 0.0 , -1.0),
-        Vec3::new( 0.0 , 0.0 , -1.0),       
-        Vec3::new( 0.0 , 0.0 , -1.0),            
-        Vec3::new( 0.0 , 0.0 , -1.0),
-    ]];
-
-
-   
-
-    // For loop for each face to render
-    for i in vfaces {
-
-        // Push all corresponded vertices of the face
-        vertices.extend_from_slice(
-            &mut positions[*i]
-                .iter()
-                .map(|&position| position + Vec3::new(x as f32, y as f32, z as f32))
-                .collect::<Vec<_>>(),
-        );
+    #[test]
+    fn chunk_mesh_data_has_every_section_at_its_height() {
+        let settings = test_settings(3);
+        let registry = BlockRegistry::default();
+
+        let chunk = generate_column(IVec2::ZERO, &settings);
+        let data = mesh_chunk(&chunk, &Neighbors::new(), &registry, MeshPass::Opaque, settings.mesher);
+
+        assert!(!data.is_empty());
+        assert_eq!(data.normals.len(), data.positions.len());
+        assert_eq!(data.uvs.len(), data.positions.len());
+        assert_eq!(data.colors.len(), data.positions.len());
+        assert_eq!(data.tiles.len(), data.positions.len());
+        assert_eq!(data.indices.len(), data.positions.len() / 4 * 6);
+        assert!(data.indices.iter().all(|index| (*index as usize) < data.positions.len()));
+
+        // The ground is in the middle section, so every vertex is between the bottom of the lowest section and the top of the highest one
+        let sections = test_sections(&settings);
+        let lowest : f32 = (sections.start() * SECTION_HEIGHT) as f32 - 0.5;
+        let highest : f32 = ((sections.end() + 1) * SECTION_HEIGHT) as f32 - 0.5;
+        assert!(data.positions.iter().all(|position| position[1] >= lowest && position[1] <= highest));
+        assert!(data.positions.iter().any(|position| position[1] > (sections.start() + 1) as f32 * SECTION_HEIGHT as f32));
+
+        // The Bevy mesh is the same data
+        let vertices : usize = data.positions.len();
+        let mesh = Mesh::from(data);
+        assert_eq!(mesh.count_vertices(), vertices);
+        assert!(mesh.attribute(Mesh::ATTRIBUTE_UV_0).is_some());
+        assert!(mesh.attribute(ATTRIBUTE_TILE).is_some());
+    }
 
-        // Push all corresponded Normals  of the face
-        normals.extend_from_slice(&normal[*i]);
-        
+    #[test]
+    fn textured_faces_get_the_tile_of_their_side() {
+        let settings = test_settings(0);
+        let width : i32 = settings.chunk_width;
+        let positions : Vec<IVec2> = (-1..=1).flat_map(|x| (-1..=1).map(move |z| IVec2::new(x, z) * width)).collect();
+        let mut world = flat_world(&positions, &settings);
+        let registry = BlockRegistry::default();
+
+        // A log and a pool of water on top of the grass, which is the layer at y 3
+        assert!(world.set_block(IVec3::new(5, 4, 9), BLOCK_LOG, &registry));
+        assert!(world.set_block(IVec3::new(2, 3, 2), BLOCK_WATER, &registry));
+
+        let chunk = world.chunk(IVec2::ZERO).unwrap();
+        let data = mesh_chunk(chunk, &find_neighbors(chunk, &world), &registry, MeshPass::Opaque, settings.mesher);
+        let tiles = |block: BlockId| registry.get(block).texture.unwrap();
+
+        for (normal, position, colors, tile) in mesher::single_faces(&data) {
+            let expected : u32 = match world.get_block(position).unwrap() {
+                // The log has rings on top and bark on its sides
+                BLOCK_LOG if normal.y == 1 => tiles(BLOCK_LOG).top,
+                BLOCK_LOG => tiles(BLOCK_LOG).side,
+                // Grass is green on top, the sides around the pool show the dirt below the grass
+                BLOCK_GRASS if normal.y == 1 => tiles(BLOCK_GRASS).top,
+                BLOCK_GRASS => tiles(BLOCK_GRASS).side,
+                block => tiles(block).side,
+            };
+
+            assert_eq!(tile, expected, "{:?} {:?}", normal, position);
+            // The texture gives the color, the vertices only darken it
+            assert!(colors.iter().all(|color| color.x == color.y && color.y == color.z), "{:?}", colors);
+        }
+
+        // The uvs of a merged face count blocks, so the tile repeats once per block instead of being stretched over the face
+        for (corners, uvs) in data.positions.chunks(4).zip(data.uvs.chunks(4)) {
+            let (min, max) = mesher::quad_bounds(corners);
+            let size : Vec3 = max - min;
+            let uv_max : Vec2 = uvs.iter().fold(Vec2::ZERO, |uv_max, uv| uv_max.max(Vec2::from(*uv)));
 
+            assert_eq!(uv_max.x * uv_max.y, size.x.max(1.0) * size.y.max(1.0) * size.z.max(1.0));
+            assert!(uvs.iter().all(|uv| uv[0] >= 0.0 && uv[1] >= 0.0));
         }
+
+        // Water has no texture and keeps its color
+        let data = mesh_chunk(chunk, &find_neighbors(chunk, &world), &registry, MeshPass::Transparent, settings.mesher);
+        assert!(!data.is_empty());
+        assert!(data.tiles.iter().all(|tile| *tile == NO_TEXTURE));
+        assert!(data.colors.iter().all(|color| color[3] == registry.get(BLOCK_WATER).color.w));
     }
 
-fn partial_cmp(one: &Vec3, other: &Vec3) -> Option<Ordering> {
-        // Compare the x, y, and z components of the vectors.
-        match one.x.partial_cmp(&other.x) {
-            Some(Ordering::Equal) => match one.y.partial_cmp(&other.y) {
-                Some(Ordering::Equal) => one.z.partial_cmp(&other.z),
-                other => other,
-            },
-            other => other,
-        }
-    
-    }   
-
-pub trait RemoveMultiple<T> {
-        /// Remove multiple indices
-        fn remove_multiple(&mut self, to_remove: Vec<usize>);
-    
-        /// Remove multiple indices with swap_remove, this is faster but reorders elements
-        fn swap_remove_multiple(&mut self, to_remove: Vec<usize>);
-    
-        /// Remove and return multiple indices
-        fn take_multiple(&mut self, to_remove: Vec<usize>) -> Vec<T>;
-    
-        /// Remove and return multiple indices, preserving the order specified in the index list
-        fn take_multiple_in_order(&mut self, to_remove: &[usize]) -> Vec<T>;
-    
-        /// Remove and return multiple indices with swap_remove, this is faster but reorders elements and the results are in reverse order
-        fn swap_take_multiple(&mut self, to_remove: Vec<usize>) -> Vec<T>;
-    }
-    
-impl<T> RemoveMultiple<T> for Vec<T> {
-        fn remove_multiple(&mut self, mut to_remove: Vec<usize>) {
-            to_remove.sort();
-            to_remove.reverse();
-            for r in to_remove {
-                self.remove(r);
+    #[test]
+    fn unloaded_sections_come_back_the_same() {
+        let settings = test_settings(7);
+        let mut test = test_world(&settings);
+
+        for x in -1..=1 {
+            for z in -1..=1 {
+                test.generate_column(IVec2::new(x, z) * settings.chunk_width);
             }
         }
-    
-        fn swap_remove_multiple(&mut self, mut to_remove: Vec<usize>) {
-            to_remove.sort();
-            to_remove.reverse();
-            for r in to_remove {
-                self.swap_remove(r);
+
+        let heights = |world: &VoxelWorld| -> Vec<Option<i32>> { world.chunk(IVec2::ZERO).unwrap().heightmap.clone() };
+        let before : (Vec<BlockId>, Vec<Option<i32>>) = (chunk_blocks(test.world.chunk(IVec2::ZERO).unwrap()), heights(&test.world));
+
+        // The ground and the trees are in the middle section
+        let position = IVec3::new(0, SECTION_HEIGHT, 0);
+        unload_section(position, &mut test.world, &mut test.pending, &test.registry);
+
+        let chunk = test.world.chunk(IVec2::ZERO).unwrap();
+        assert!(!chunk.has_section(1));
+        assert!(chunk.heightmap.iter().all(|height| height.is_none_or(|height| height <= SECTION_HEIGHT)));
+
+        test.generate(position);
+
+        assert!(before == (chunk_blocks(test.world.chunk(IVec2::ZERO).unwrap()), heights(&test.world)));
+    }
+
+    #[test]
+    fn reloaded_chunks_get_the_decorations_of_their_neighbors_back() {
+        let settings = test_settings(7);
+        let mut test = test_world(&settings);
+
+        for x in -1..=1 {
+            for z in -1..=1 {
+                test.generate_column(IVec2::new(x, z) * settings.chunk_width);
             }
         }
-    
-        fn take_multiple(&mut self, mut to_remove: Vec<usize>) -> Vec<T> {
-            to_remove.sort();
-            to_remove.reverse();
-            let mut collected = vec![];
-            for r in to_remove {
-                collected.push(self.remove(r));
-            }
-            collected.reverse();
-            collected
-        }
-    
-        fn take_multiple_in_order(&mut self, to_remove: &[usize]) -> Vec<T> {
-            let mut to_remove = to_remove.iter().copied().enumerate().collect::<Vec<_>>();
-            to_remove.sort_by_key(|(_, r)| *r);
-            to_remove.reverse();
-            let mut collected : Vec<Option<T>> = std::iter::repeat_with(|| None).take(to_remove.len()).collect();
-            for (i, r) in to_remove {
-                collected[i] = Some(self.remove(r));
-            }
-            collected.into_iter().filter_map(|x| x).collect()
-        }
-    
-        fn swap_take_multiple(&mut self, mut to_remove: Vec<usize>) -> Vec<T> {
-            to_remove.sort();
-            to_remove.reverse();
-            let mut collected = vec![];
-            for r in to_remove {
-                collected.push(self.swap_remove(r));
+
+        let before : Vec<Vec<BlockId>> = [-1, 0, 1].iter().map(|x| chunk_blocks(test.world.chunk(IVec2::new(*x, 0) * settings.chunk_width).unwrap())).collect();
+
+        // Unload the middle chunk and its east neighbor and load them again in the other order
+        unload_chunk(IVec2::ZERO, &mut test.world, &mut test.pending);
+        unload_chunk(IVec2::new(settings.chunk_width, 0), &mut test.world, &mut test.pending);
+        assert!(test.world.chunk(IVec2::ZERO).is_none());
+
+        test.generate_column(IVec2::new(settings.chunk_width, 0));
+        test.generate_column(IVec2::ZERO);
+
+        let after : Vec<Vec<BlockId>> = [-1, 0, 1].iter().map(|x| chunk_blocks(test.world.chunk(IVec2::new(*x, 0) * settings.chunk_width).unwrap())).collect();
+
+        assert!(before == after);
+    }
+
+    // App with the resources of the WorldPlugin but without rendering, the systems are added by the tests
+    // The classic flat world keeps the generation fast, only its lowest section has visible blocks
+    fn streaming_app(settings: &WorldGenSettings) -> App {
+        AsyncComputeTaskPool::get_or_init(TaskPool::default);
+
+        let settings = flat_settings(settings);
+        let mut app = App::new();
+
+        app.insert_resource(BiomeMap::new(&settings))
+            .insert_resource(WorldGenerator(settings.generator.build(&settings).unwrap().into()))
+            .insert_resource(VoxelWorld::new(&settings))
+            .insert_resource(settings)
+            .init_resource::<BlockRegistry>()
+            .init_resource::<PendingWrites>()
+            .init_resource::<ChunkTasks>()
+            .init_resource::<SectionEntities>()
+            .init_resource::<Assets<Mesh>>()
+            .insert_resource(ChunkMaterials { opaque: Handle::default(), transparent: Handle::default() });
+
+        app
+    }
+
+    // Run frames until the condition holds, the tasks run on other threads so it can take a few
+    fn update_until(app: &mut App, condition: impl Fn(&World) -> bool) {
+        for _ in 0..1000 {
+            app.update();
+
+            if condition(&app.world) {
+                return;
             }
-            collected
+
+            std::thread::sleep(std::time::Duration::from_millis(1));
         }
-}
 
-impl Plugin for WorldPlugin {
-    fn build(&self, app: &mut App) {
-        app.add_systems(Startup, spawn_chunks);
+        panic!("the world didn't reach the expected state");
+    }
+
+    // The tests have no transform propagation, so the global transform gets set right away
+    fn move_loader(app: &mut App, loader: Entity, position: Vec3) {
+        *app.world.get_mut::<GlobalTransform>(loader).unwrap() = GlobalTransform::from_translation(position);
+    }
+
+    // Check if the sections of the chunks are generated and meshed and no task is running anymore
+    fn is_streamed(world: &World, chunks: &[IVec2], sections: std::ops::RangeInclusive<i32>) -> bool {
+        let voxel_world = world.resource::<VoxelWorld>();
+        let section_entities = world.resource::<SectionEntities>();
+        let tasks = world.resource::<ChunkTasks>();
+
+        let loaded : bool = chunks.iter().all(|position| {
+            sections.clone().all(|section_y| {
+                voxel_world.chunk(*position).is_some_and(|chunk| chunk.has_section(section_y))
+                    && section_entities.entities.contains_key(&(*position, section_y))
+            })
+        });
+
+        loaded && tasks.generating.is_empty() && tasks.meshing.is_empty()
     }
-}
 
+    // Positions of the chunks from min to max, in chunks
+    fn chunk_positions(min: IVec2, max: IVec2, settings: &WorldGenSettings) -> Vec<IVec2> {
+        (min.x..=max.x)
+            .flat_map(|x| (min.y..=max.y).map(move |z| IVec2::new(x, z) * settings.chunk_width))
+            .collect()
+    }
+
+    #[test]
+    fn chunks_get_streamed_around_the_chunk_loader() {
+        let settings = WorldGenSettings { render_distance: 1, unload_distance: 2, ..test_settings(0) };
+        let width : f32 = settings.chunk_width as f32;
+        let mut app = streaming_app(&settings);
+
+        app.add_systems(Update, (stream_chunks, insert_generated_sections, queue_dirty_sections, upload_section_meshes).chain());
+
+        // In the middle of the chunk at -1, -1 and of the section at 0, so all loaded chunks are at negative coordinates
+        let loader : Entity = app.world.spawn((ChunkLoader, GlobalTransform::from_translation(Vec3::new(-0.5 * width, 8.0, -0.5 * width)))).id();
+        let first : Vec<IVec2> = chunk_positions(IVec2::new(-2, -2), IVec2::new(0, 0), &settings);
+
+        update_until(&mut app, |world| is_streamed(world, &first, -1..=1));
+
+        let chunk_entities = |app: &mut App| -> Vec<IVec2> { app.world.query::<&ChunkEntity>().iter(&app.world).map(|chunk| chunk.position).collect() };
+        let mesh_entities = |app: &App, x: i32| -> Vec<Entity> {
+            app.world.resource::<SectionEntities>().entities
+                .iter()
+                .filter(|((position, _), _)| position.x == x * settings.chunk_width)
+                .flat_map(|(_, entities)| entities.iter().copied())
+                .collect()
+        };
+
+        assert_eq!(app.world.resource::<VoxelWorld>().len(), 9);
+        assert_eq!(chunk_entities(&mut app).len(), 9);
+
+        // The flat ground is in the section at 0
+        assert!(first.iter().all(|position| !app.world.resource::<SectionEntities>().entities[&(*position, 0)].is_empty()));
+
+        // Two chunks to the east, the chunks at -1 are out of the render distance but still inside of the unload distance
+        let west : Vec<Entity> = mesh_entities(&app, -2);
+        assert!(!west.is_empty());
+
+        move_loader(&mut app, loader, Vec3::new(1.5 * width, 8.0, -0.5 * width));
+        let second : Vec<IVec2> = chunk_positions(IVec2::new(0, -2), IVec2::new(2, 0), &settings);
+
+        update_until(&mut app, |world| is_streamed(world, &second, -1..=1));
+
+        let world = app.world.resource::<VoxelWorld>();
+        assert!(chunk_positions(IVec2::new(-2, -2), IVec2::new(-2, 0), &settings).iter().all(|position| world.chunk(*position).is_none()));
+        assert!(chunk_positions(IVec2::new(-1, -2), IVec2::new(-1, 0), &settings).iter().all(|position| world.chunk(*position).is_some()));
+        assert_eq!(world.len(), 12);
+
+        assert!(!chunk_entities(&mut app).iter().any(|position| position.x == -2 * settings.chunk_width));
+        assert!(mesh_entities(&app, -2).is_empty());
+        assert!(west.iter().all(|entity| app.world.get_entity(*entity).is_none()));
+
+        // Three sections up, the sections at -1 and 0 are too far below and the section at 1 is inside of the unload distance
+        let ground : Vec<Entity> = second.iter().flat_map(|position| app.world.resource::<SectionEntities>().entities[&(*position, 0)].clone()).collect();
+
+        move_loader(&mut app, loader, Vec3::new(1.5 * width, 8.0 + 3.0 * SECTION_HEIGHT as f32, -0.5 * width));
+
+        update_until(&mut app, |world| is_streamed(world, &second, 2..=4));
+
+        let world = app.world.resource::<VoxelWorld>();
+        let section_entities = app.world.resource::<SectionEntities>();
+
+        for position in second.iter() {
+            let chunk = world.chunk(*position).unwrap();
+
+            assert!(!chunk.has_section(-1) && !chunk.has_section(0));
+            assert!(chunk.has_section(1));
+            assert!(!section_entities.entities.contains_key(&(*position, -1)) && !section_entities.entities.contains_key(&(*position, 0)));
+        }
+
+        assert!(ground.iter().all(|entity| app.world.get_entity(*entity).is_none()));
+    }
+
+    #[test]
+    fn tasks_of_sections_out_of_range_get_cancelled() {
+        let settings = WorldGenSettings { render_distance: 1, unload_distance: 2, ..test_settings(0) };
+        let width : i32 = settings.chunk_width;
+        let mut app = streaming_app(&settings);
+
+        // Without the systems which take the finished tasks, every started task stays in ChunkTasks
+        app.add_systems(Update, (stream_chunks, queue_dirty_sections).chain());
+
+        // Only the ground sections are there, so the sections above and below them get generated while the ground gets meshed
+        let mut test = test_world(&flat_settings(&settings));
+
+        for position in chunk_positions(IVec2::new(-1, -1), IVec2::new(1, 1), &settings) {
+            test.generate(IVec3::new(position.x, 0, position.y));
+            app.world.spawn(ChunkEntity { position });
+        }
+
+        app.insert_resource(test.world);
+
+        let loader : Entity = app.world.spawn((ChunkLoader, GlobalTransform::from_translation(Vec3::new(8.0, 8.0, 8.0)))).id();
+        app.update();
+
+        let tasks = app.world.resource::<ChunkTasks>();
+        assert!(!tasks.generating.is_empty());
+        assert!(!tasks.meshing.is_empty());
+
+        // Ten chunks to the east, only the sections around the chunk at 10, 0 are in range
+        move_loader(&mut app, loader, Vec3::new(8.0 + 10.0 * width as f32, 8.0, 8.0));
+        app.update();
+
+        let tasks = app.world.resource::<ChunkTasks>();
+        assert!(!tasks.generating.is_empty());
+        assert!(tasks.generating.keys().all(|position| position.x >= 8 * width));
+        assert!(tasks.meshing.is_empty());
+
+        // Ten sections up, the sections around the ground are too far below
+        move_loader(&mut app, loader, Vec3::new(8.0 + 10.0 * width as f32, 8.0 + 10.0 * SECTION_HEIGHT as f32, 8.0));
+        app.update();
+
+        let tasks = app.world.resource::<ChunkTasks>();
+        assert!(!tasks.generating.is_empty());
+        assert!(tasks.generating.keys().all(|position| position.y >= 8 * SECTION_HEIGHT));
+    }
+
+    // Task which is already finished when it gets returned
+    fn finished_task<T: Send + 'static>(result: T) -> Task<T> {
+        let task = AsyncComputeTaskPool::get().spawn(async move { result });
+
+        while !task.is_finished() {
+            std::thread::yield_now();
+        }
+
+        task
+    }
+
+    #[test]
+    fn finished_tasks_are_added_within_the_upload_budget() {
+        let settings = WorldGenSettings { max_uploads_per_frame: 3, ..test_settings(0) };
+        let mut app = streaming_app(&settings);
+
+        app.add_systems(Update, (insert_generated_sections, upload_section_meshes).chain());
+
+        // The ground sections of ten chunks in a row
+        let test = test_world(&flat_settings(&settings));
+        let positions: Vec<IVec3> = (0..10).map(|x| IVec3::new(x * settings.chunk_width, 0, 0)).collect();
+
+        for position in positions.iter() {
+            let generated = build_section(*position, test.generator.as_ref(), &test.biomes, &test.registry, &test.settings);
+            app.world.resource_mut::<ChunkTasks>().generating.insert(*position, finished_task(generated));
+        }
+
+        for inserted in [3, 6, 9, 10] {
+            app.update();
+
+            assert_eq!(app.world.resource::<VoxelWorld>().len(), inserted);
+            assert_eq!(app.world.resource::<ChunkTasks>().generating.len(), positions.len() - inserted);
+        }
+
+        for position in positions.iter() {
+            let chunk_position = IVec2::new(position.x, position.z);
+            let section : Section = app.world.resource::<VoxelWorld>().chunk(chunk_position).unwrap().sections[&0].clone();
+            let section_meshes = create_section_meshes(&section, &NeighborSections::new(), &test.registry, &test.settings);
+
+            app.world.resource_mut::<ChunkTasks>().meshing.insert((chunk_position, 0), finished_task(section_meshes));
+        }
+
+        for uploaded in [3, 6, 9, 10] {
+            app.update();
+
+            assert_eq!(app.world.resource::<SectionEntities>().entities.len(), uploaded);
+            assert_eq!(app.world.resource::<ChunkTasks>().meshing.len(), positions.len() - uploaded);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use bevy::pbr::{ExtendedMaterial, MaterialExtension, MaterialExtensionKey, MaterialExtensionPipeline};
+use bevy::prelude::*;
+use bevy::render::mesh::{MeshVertexAttribute, MeshVertexBufferLayout};
+use bevy::render::render_resource::{AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError, VertexFormat};
+
+// Path of the block texture atlas inside the assets folder
+pub const ATLAS_PATH : &str = "textures/blocks.png";
+
+// The atlas is a square grid of tiles, tiles are counted row by row from the top left
+pub const ATLAS_COLUMNS : u32 = 8;
+
+// Tile of the faces of blocks without a texture, they only show their vertex color
+pub const NO_TEXTURE : u32 = u32::MAX;
+
+// Tile of the atlas for every vertex, all four vertices of a face have the same tile
+pub const ATTRIBUTE_TILE : MeshVertexAttribute = MeshVertexAttribute::new("Vertex_Tile", 410_392_861, VertexFormat::Uint32);
+
+const SHADER_PATH : &str = "shaders/voxel.wgsl";
+
+// Shader location of the tile, the standard attributes use the locations up to 7
+const TILE_SHADER_LOCATION : u32 = 8;
+
+// Material of the chunk meshes, the standard material with the block textures taken from the atlas
+pub type VoxelMaterial = ExtendedMaterial<StandardMaterial, BlockAtlas>;
+
+// Texture atlas of all block tiles, added on top of the standard material
+#[derive(Asset, AsBindGroup, Reflect, Clone, Debug)]
+pub struct BlockAtlas {
+    // Number of tiles in a row of the atlas
+    #[uniform(100)]
+    pub columns: u32,
+    #[texture(101)]
+    #[sampler(102)]
+    pub texture: Handle<Image>,
+}
+
+impl BlockAtlas {
+    pub fn new(texture: Handle<Image>) -> Self {
+        BlockAtlas { columns: ATLAS_COLUMNS, texture }
+    }
+}
+
+impl MaterialExtension for BlockAtlas {
+    fn vertex_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    // The standard pipeline only passes the standard attributes to the shaders, so the tile gets added to them
+    // The prepass shaders don't read it, which is fine for an attribute
+    fn specialize(
+        _pipeline: &MaterialExtensionPipeline,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialExtensionKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let tile_layout = layout.get_layout(&[ATTRIBUTE_TILE.at_shader_location(TILE_SHADER_LOCATION)])?;
+        descriptor.vertex.buffers[0].attributes.extend(tile_layout.attributes);
+
+        Ok(())
+    }
+}
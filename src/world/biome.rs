@@ -0,0 +1,238 @@
+use bevy::prelude::*;
+
+use super::{BlockId, NoiseStream, NoiseType, TerrainNoise, WorldGenSettings};
+use super::{BLOCK_DIRT, BLOCK_GRASS, BLOCK_GRAVEL, BLOCK_SAND, BLOCK_SANDSTONE, BLOCK_SNOW};
+
+// Width of the transition between two biomes in climate space
+// Smaller values give sharper borders, bigger values wider and smoother blends
+const BIOME_BLEND : f64 = 0.25;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Biome {
+    Plains,
+    Desert,
+    Mountains,
+    Tundra,
+    Ocean,
+}
+
+pub const BIOMES : [Biome; 5] = [Biome::Plains, Biome::Desert, Biome::Mountains, Biome::Tundra, Biome::Ocean];
+
+// Entry of the biome table
+pub struct BiomeParams {
+    // Position of the biome in the climate map (both -1..1)
+    pub temperature: f64,
+    pub humidity: f64,
+
+    // Shape of the terrain: ground level + height_offset + height_curve(noise) * amplitude
+    pub height_offset: f64,
+    pub amplitude: f64,
+    pub height_curve: fn(f64) -> f64,
+
+    // Top block of a column and the blocks right under it
+    pub surface_block: BlockId,
+    pub filler_block: BlockId,
+    pub filler_depth: i32,
+}
+
+impl Biome {
+    pub fn params(&self) -> BiomeParams {
+        match self {
+            Biome::Plains => BiomeParams {
+                temperature: 0.1,
+                humidity: 0.0,
+                height_offset: 0.0,
+                amplitude: 0.5,
+                height_curve: |value| value,
+                surface_block: BLOCK_GRASS,
+                filler_block: BLOCK_DIRT,
+                filler_depth: 3,
+            },
+            Biome::Desert => BiomeParams {
+                temperature: 0.7,
+                humidity: -0.6,
+                height_offset: 2.0,
+                amplitude: 0.6,
+                // Soft dunes
+                height_curve: |value| value.abs(),
+                surface_block: BLOCK_SAND,
+                filler_block: BLOCK_SANDSTONE,
+                filler_depth: 4,
+            },
+            Biome::Mountains => BiomeParams {
+                temperature: -0.3,
+                humidity: -0.4,
+                height_offset: 12.0,
+                amplitude: 4.0,
+                // Flat valleys and steep peaks
+                height_curve: |value| value.signum() * value.abs().powf(1.5),
+                surface_block: BLOCK_GRAVEL,
+                filler_block: BLOCK_GRAVEL,
+                filler_depth: 1,
+            },
+            Biome::Tundra => BiomeParams {
+                temperature: -0.7,
+                humidity: 0.2,
+                height_offset: 3.0,
+                amplitude: 0.8,
+                height_curve: |value| value,
+                surface_block: BLOCK_SNOW,
+                filler_block: BLOCK_DIRT,
+                filler_depth: 3,
+            },
+            Biome::Ocean => BiomeParams {
+                temperature: 0.2,
+                humidity: 0.7,
+                height_offset: -16.0,
+                amplitude: 0.3,
+                height_curve: |value| value,
+                surface_block: BLOCK_SAND,
+                filler_block: BLOCK_SAND,
+                filler_depth: 3,
+            },
+        }
+    }
+}
+
+// Temperature and humidity maps of the world
+// Every position gets a climate, the biomes closest to that climate decide how the terrain looks
+#[derive(Resource, Clone)]
+pub struct BiomeMap {
+    temperature: TerrainNoise,
+    humidity: TerrainNoise,
+}
+
+impl BiomeMap {
+    pub fn new(settings: &WorldGenSettings) -> Self {
+        // Own noise streams, so the climate maps don't look like the terrain noise
+        let temperature = TerrainNoise::new(settings.seed, NoiseStream::Temperature, 2, settings.biome_scale, 2.0, 0.5, NoiseType::Standard);
+        let humidity = TerrainNoise::new(settings.seed, NoiseStream::Humidity, 2, settings.biome_scale, 2.0, 0.5, NoiseType::Standard);
+
+        BiomeMap { temperature, humidity }
+    }
+
+    // Temperature and humidity at x, z
+    pub fn climate_at(&self, x: i32, z: i32) -> (f64, f64) {
+        // The noise rarely reaches -1 or 1, so stretch it to use the whole climate map
+        let temperature : f64 = (self.temperature.get_2d(x as f64, z as f64) * 2.0).clamp(-1.0, 1.0);
+        let humidity : f64 = (self.humidity.get_2d(x as f64, z as f64) * 2.0).clamp(-1.0, 1.0);
+
+        (temperature, humidity)
+    }
+
+    // Influence of every biome at x, z, the weights add up to 1
+    pub fn weights_at(&self, x: i32, z: i32) -> [(Biome, f64); 5] {
+        let (temperature, humidity) = self.climate_at(x, z);
+
+        let mut weights : [(Biome, f64); 5] = BIOMES.map(|biome| {
+            let params = biome.params();
+            let distance_squared : f64 = (params.temperature - temperature).powi(2) + (params.humidity - humidity).powi(2);
+
+            (biome, (-distance_squared / (BIOME_BLEND * BIOME_BLEND)).exp())
+        });
+
+        let total : f64 = weights.iter().map(|(_, weight)| weight).sum();
+
+        for (_, weight) in &mut weights {
+            *weight /= total;
+        }
+
+        weights
+    }
+
+    // The biome with the most influence at x, z
+    pub fn biome_at(&self, x: i32, z: i32) -> Biome {
+        dominant_biome(&self.weights_at(x, z))
+    }
+}
+
+impl BiomeParams {
+    // Height of the terrain of this biome above the ground level at a height noise value
+    pub fn height(&self, value: f64, amplitude: f64) -> f64 {
+        self.height_offset + (self.height_curve)(value) * self.amplitude * amplitude
+    }
+}
+
+// Height of the terrain above the ground level at a height noise value, the height curves of all biomes blended by their weights
+pub fn blended_height(weights: &[(Biome, f64); 5], value: f64, amplitude: f64) -> f64 {
+    weights
+        .iter()
+        .map(|(biome, weight)| weight * biome.params().height(value, amplitude))
+        .sum()
+}
+
+// Lowest and highest height above the ground level any blend of the biomes can reach
+// The weights add up to 1, so the blend stays between the lowest and the highest biome
+// The height curves only rise or fall on each side of 0, so their extremes are at -1, 0 and 1
+pub fn height_range(amplitude: f64) -> (f64, f64) {
+    BIOMES
+        .iter()
+        .flat_map(|biome| [-1.0, 0.0, 1.0].map(|value| biome.params().height(value, amplitude)))
+        .fold((f64::MAX, f64::MIN), |(min, max), height| (min.min(height), max.max(height)))
+}
+
+pub fn dominant_biome(weights: &[(Biome, f64); 5]) -> Biome {
+    weights
+        .iter()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(biome, _)| *biome)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_settings(seed: u32) -> WorldGenSettings {
+        WorldGenSettings { seed, ..default() }
+    }
+
+    // Every 64th column of a big square around the origin
+    fn sample_positions() -> impl Iterator<Item = (i32, i32)> {
+        (-64..64).flat_map(|x| (-64..64).map(move |z| (x * 64, z * 64)))
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_biomes() {
+        let first = BiomeMap::new(&test_settings(12));
+        let second = BiomeMap::new(&test_settings(12));
+        let other = BiomeMap::new(&test_settings(13));
+
+        assert!(sample_positions().all(|(x, z)| first.biome_at(x, z) == second.biome_at(x, z)));
+        assert!(sample_positions().any(|(x, z)| first.biome_at(x, z) != other.biome_at(x, z)));
+    }
+
+    #[test]
+    fn every_biome_is_reachable() {
+        let biomes = BiomeMap::new(&test_settings(0));
+        let found: Vec<Biome> = sample_positions().map(|(x, z)| biomes.biome_at(x, z)).collect();
+
+        for biome in BIOMES {
+            assert!(found.contains(&biome), "{:?}", biome);
+        }
+    }
+
+    #[test]
+    fn blended_height_has_no_steps_at_biome_borders() {
+        // Much less than the difference between the heights of the biomes, which is up to 28 blocks
+        const MAX_STEP : f64 = 2.0;
+
+        let settings = test_settings(0);
+        let biomes = BiomeMap::new(&settings);
+        let height = |x: i32| blended_height(&biomes.weights_at(x, 0), 0.5, settings.amplitude as f64);
+
+        // All the weight on the biome which wins at the column, like the terrain would look without blending
+        let unblended = |x: i32| blended_height(&[(biomes.biome_at(x, 0), 0.2); 5], 0.5, settings.amplitude as f64);
+
+        // Columns where the biome changes from one to the next
+        let borders: Vec<i32> = (0..20000).filter(|x| biomes.biome_at(*x, 0) != biomes.biome_at(x + 1, 0)).collect();
+        assert!(borders.iter().any(|x| (unblended(x + 1) - unblended(*x)).abs() > MAX_STEP));
+
+        for border in borders {
+            for x in border - 16..border + 16 {
+                let step : f64 = (height(x + 1) - height(x)).abs();
+                assert!(step < MAX_STEP, "step of {} blocks at {}", step, x);
+            }
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+
+use super::greedy::{FaceKey, Quad};
+use super::mesher::{face_ao, is_face_covered, section_index, NeighborSections};
+use super::{BlockId, BlockRegistry, MeshPass, Section, DIRECTIONS};
+
+// Every row of blocks has to fit into the bits of an u32, one bit per block
+pub const MAX_BITMASK_WIDTH : i32 = 32;
+
+// Find the merged faces of a section with bit operations, 32 blocks at a time
+// Gives every rectangle to add_quad, with the face index of its direction and the slice it lies in
+// The result is the same as the per voxel mesher, only the order of the rectangles is different
+pub fn section_quads(
+    section: &Section,
+    neighbor_sections: &NeighborSections,
+    registry: &BlockRegistry,
+    size: IVec3,
+    pass: MeshPass,
+    add_quad: &mut impl FnMut(usize, i32, Quad),
+) {
+    // Look up the few block types of the palettes once instead of once per block, by block id
+    let palettes = || section.blocks.palette().iter().chain(neighbor_sections.values().flat_map(|neighbor| neighbor.blocks.palette()));
+    let max_id : usize = palettes().map(|block| block.0 as usize).max().unwrap_or(0);
+
+    let mut is_inside: Vec<bool> = vec![false; max_id + 1];
+    let mut is_blocking: Vec<bool> = vec![false; max_id + 1];
+
+    for block in palettes() {
+        is_inside[block.0 as usize] = pass.contains(registry, *block);
+        is_blocking[block.0 as usize] = !pass.is_exposed(registry, *block);
+    }
+
+    let blocks: Vec<BlockId> = section.blocks.iter().collect();
+
+    // One column of bits along each axis for every u, v of the slices, by axis
+    // Bit 0 and bit length + 1 are the blocks of the neighbor sections before and after the column
+    let columns = |axis: usize| vec![0u64; (size[(axis + 1) % 3] * size[(axis + 2) % 3]) as usize];
+    let mut inside: [Vec<u64>; 3] = [columns(0), columns(1), columns(2)];
+    let mut blocking: [Vec<u64>; 3] = [columns(0), columns(1), columns(2)];
+
+    // Fill the columns of all three axes in one go through the blocks
+    for (i, block) in blocks.iter().enumerate() {
+        let i : i32 = i as i32;
+        let position = IVec3::new(i % size.x, i / (size.x * size.z), (i / size.x) % size.z);
+        let block_inside : u64 = is_inside[block.0 as usize] as u64;
+        let block_blocking : u64 = is_blocking[block.0 as usize] as u64;
+
+        for axis in 0..3 {
+            let u_axis : usize = (axis + 1) % 3;
+            let v_axis : usize = (axis + 2) % 3;
+            let column : usize = (position[u_axis] + position[v_axis] * size[u_axis]) as usize;
+
+            inside[axis][column] |= block_inside << (position[axis] + 1);
+            blocking[axis][column] |= block_blocking << (position[axis] + 1);
+        }
+    }
+
+    for axis in 0..3 {
+        let u_axis : usize = (axis + 1) % 3;
+        let v_axis : usize = (axis + 2) % 3;
+        let length : i32 = size[axis];
+        let inside : &[u64] = &inside[axis];
+        let blocking : &mut [u64] = &mut blocking[axis];
+
+        // The neighbor blocks right before and after the columns
+        for (direction, bit) in [(DIRECTIONS[axis * 2 + 1], 0), (DIRECTIONS[axis * 2], length + 1)] {
+            for v in 0..size[v_axis] {
+                for u in 0..size[u_axis] {
+                    // The block of the section on its border, its neighbor in the direction is the one before or after the column
+                    let mut position = IVec3::ZERO;
+                    position[axis] = if bit == 0 { 0 } else { length - 1 };
+                    position[u_axis] = u;
+                    position[v_axis] = v;
+
+                    let neighbor_blocking : bool = is_face_covered(section, neighbor_sections, position, direction, size, |neighbor_type| is_blocking[neighbor_type.0 as usize]);
+
+                    blocking[(u + v * size[u_axis]) as usize] |= (neighbor_blocking as u64) << bit;
+                }
+            }
+        }
+
+        for face in [axis * 2, axis * 2 + 1] {
+            // Rows of bits along u of every slice, block type and ambient occlusion, ordered so the mesh is always built the same way
+            let mut planes: BTreeMap<(i32, u16, [u8; 4]), Vec<u32>> = BTreeMap::new();
+
+            for v in 0..size[v_axis] {
+                for u in 0..size[u_axis] {
+                    let column : usize = (u + v * size[u_axis]) as usize;
+
+                    // A face is visible where the block is meshed and the block next to it doesn't hide it
+                    let mut faces : u64 = if face == axis * 2 {
+                        inside[column] & !(blocking[column] >> 1)
+                    } else {
+                        inside[column] & !(blocking[column] << 1)
+                    };
+
+                    while faces != 0 {
+                        let slice : i32 = faces.trailing_zeros() as i32 - 1;
+                        faces &= faces - 1;
+
+                        let mut position = IVec3::ZERO;
+                        position[axis] = slice;
+                        position[u_axis] = u;
+                        position[v_axis] = v;
+
+                        let block : BlockId = blocks[section_index(position, size)];
+                        let ao : [u8; 4] = face_ao(section, neighbor_sections, registry, position, face, size);
+                        let rows = planes.entry((slice, block.0, ao)).or_insert_with(|| vec![0; size[v_axis] as usize]);
+                        rows[v as usize] |= 1 << u;
+                    }
+                }
+            }
+
+            for ((slice, block, ao), mut rows) in planes {
+                for quad in greedy_rows(&mut rows, FaceKey { block: BlockId(block), ao }) {
+                    add_quad(face, slice, quad);
+                }
+            }
+        }
+    }
+}
+
+// Merge the faces which look the same in a slice into rectangles, one u32 of faces along u per row
+// Runs of faces are found with trailing_zeros and trailing_ones and the next rows are checked with a single and
+fn greedy_rows(rows: &mut [u32], key: FaceKey) -> Vec<Quad> {
+    let mut quads: Vec<Quad> = Vec::new();
+
+    for v in 0..rows.len() {
+        while rows[v] != 0 {
+            let u : u32 = rows[v].trailing_zeros();
+            let width : u32 = (rows[v] >> u).trailing_ones();
+            let run : u32 = (u32::MAX >> (32 - width)) << u;
+
+            let mut height : usize = 1;
+
+            while v + height < rows.len() && rows[v + height] & run == run {
+                rows[v + height] &= !run;
+                height += 1;
+            }
+
+            rows[v] &= !run;
+
+            quads.push(Quad { u: u as i32, v: v as i32, width: width as i32, height: height as i32, key });
+        }
+    }
+
+    quads
+}
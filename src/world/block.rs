@@ -0,0 +1,189 @@
+use bevy::prelude::*;
+
+use super::Direction;
+
+// Compact id of a block type, the index of its definition in the block registry
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct BlockId(pub u16);
+
+// BLOCK TYPES
+// The blocks the world generation places, registered in this order by BlockRegistry::default
+pub const BLOCK_AIR : BlockId = BlockId(0);
+pub const BLOCK_STONE : BlockId = BlockId(1);
+pub const BLOCK_GRASS : BlockId = BlockId(2);
+pub const BLOCK_DIRT : BlockId = BlockId(3);
+pub const BLOCK_SAND : BlockId = BlockId(4);
+pub const BLOCK_SANDSTONE : BlockId = BlockId(5);
+pub const BLOCK_GRAVEL : BlockId = BlockId(6);
+pub const BLOCK_SNOW : BlockId = BlockId(7);
+pub const BLOCK_BEDROCK : BlockId = BlockId(8);
+pub const BLOCK_COAL_ORE : BlockId = BlockId(9);
+pub const BLOCK_IRON_ORE : BlockId = BlockId(10);
+pub const BLOCK_GOLD_ORE : BlockId = BlockId(11);
+pub const BLOCK_DIAMOND_ORE : BlockId = BlockId(12);
+pub const BLOCK_WATER : BlockId = BlockId(13);
+pub const BLOCK_LOG : BlockId = BlockId(14);
+pub const BLOCK_LEAVES : BlockId = BlockId(15);
+pub const BLOCK_TALL_GRASS : BlockId = BlockId(16);
+pub const BLOCK_CACTUS : BlockId = BlockId(17);
+
+// Everything the game needs to know about a block type
+#[derive(Clone, PartialEq, Debug)]
+pub struct BlockDefinition {
+    pub name: String,
+    // Visible blocks get meshed, air is the only invisible block so far
+    pub visible: bool,
+    // Solid blocks are part of the terrain surface, you can't walk through them
+    pub solid: bool,
+    // Faces next to transparent blocks are not hidden, transparent blocks get their own mesh
+    pub transparent: bool,
+    pub color: Vec4,
+    // Tiles of the texture atlas, the color is used as long as there is no texture
+    pub texture: Option<BlockTextures>,
+    // How long it takes to break the block, infinite for unbreakable blocks
+    pub hardness: f32,
+    // Light level the block gives off, 0 for blocks that don't glow
+    pub light_emission: u8,
+}
+
+impl BlockDefinition {
+    // A normal solid block with the given color
+    pub fn new(name: &str, color: Vec4, hardness: f32) -> Self {
+        BlockDefinition {
+            name: name.to_string(),
+            visible: true,
+            solid: true,
+            transparent: false,
+            color,
+            texture: None,
+            hardness,
+            light_emission: 0,
+        }
+    }
+
+    // The same block with a texture instead of its color
+    pub fn with_texture(self, texture: BlockTextures) -> Self {
+        BlockDefinition { texture: Some(texture), ..self }
+    }
+}
+
+// Tiles of the texture atlas on the faces of a block, counted row by row from the top left of the atlas
+// Most blocks look the same from every side, blocks like grass have their own tiles for the top and bottom
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BlockTextures {
+    pub top: u32,
+    pub side: u32,
+    pub bottom: u32,
+}
+
+impl BlockTextures {
+    pub fn new(top: u32, side: u32, bottom: u32) -> Self {
+        BlockTextures { top, side, bottom }
+    }
+
+    // The same tile on every face
+    pub fn all(tile: u32) -> Self {
+        BlockTextures::new(tile, tile, tile)
+    }
+
+    // Tile of the face which looks in the direction
+    pub fn tile(&self, direction: Direction) -> u32 {
+        match direction {
+            Direction::Up => self.top,
+            Direction::Down => self.bottom,
+            _ => self.side,
+        }
+    }
+}
+
+// All block types of the game, looked up by their id
+#[derive(Resource, Clone)]
+pub struct BlockRegistry {
+    blocks: Vec<BlockDefinition>,
+    // Used for ids without a definition, so a missing block shows up as bright magenta instead of crashing
+    missing: BlockDefinition,
+}
+
+impl BlockRegistry {
+    // An empty registry without any blocks, not even air
+    pub fn empty() -> Self {
+        BlockRegistry {
+            blocks: Vec::new(),
+            missing: BlockDefinition::new("missing", Vec4::new(1.0, 0.0, 1.0, 1.0), 0.0),
+        }
+    }
+
+    // Add a block type and get the id it can be placed with
+    pub fn register(&mut self, definition: BlockDefinition) -> BlockId {
+        let id = BlockId(self.blocks.len() as u16);
+        self.blocks.push(definition);
+
+        id
+    }
+
+    // Replace the block type with the same name, or add it if there is none yet
+    pub fn insert(&mut self, definition: BlockDefinition) -> BlockId {
+        match self.id(&definition.name) {
+            Some(id) => {
+                self.blocks[id.0 as usize] = definition;
+                id
+            }
+            None => self.register(definition),
+        }
+    }
+
+    pub fn get(&self, id: BlockId) -> &BlockDefinition {
+        self.blocks.get(id.0 as usize).unwrap_or(&self.missing)
+    }
+
+    // Find the id of a block type by its name
+    pub fn id(&self, name: &str) -> Option<BlockId> {
+        self.blocks
+            .iter()
+            .position(|definition| definition.name == name)
+            .map(|index| BlockId(index as u16))
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+// The registry with all block types of the world generation
+impl Default for BlockRegistry {
+    fn default() -> Self {
+        let mut registry = BlockRegistry::empty();
+
+        // The tiles are the ones of the block texture atlas in the assets, water has no texture and uses its color
+        let blocks : [(BlockId, BlockDefinition); 18] = [
+            (BLOCK_AIR, BlockDefinition { visible: false, solid: false, transparent: true, ..BlockDefinition::new("air", Vec4::ZERO, 0.0) }),
+            (BLOCK_STONE, BlockDefinition::new("stone", Vec4::new(0.5, 0.5, 0.5, 1.0), 1.5).with_texture(BlockTextures::all(0))),
+            (BLOCK_GRASS, BlockDefinition::new("grass", Vec4::new(0.3, 0.65, 0.2, 1.0), 0.6).with_texture(BlockTextures::new(1, 2, 3))),
+            (BLOCK_DIRT, BlockDefinition::new("dirt", Vec4::new(0.45, 0.3, 0.15, 1.0), 0.5).with_texture(BlockTextures::all(3))),
+            (BLOCK_SAND, BlockDefinition::new("sand", Vec4::new(0.9, 0.85, 0.55, 1.0), 0.5).with_texture(BlockTextures::all(4))),
+            (BLOCK_SANDSTONE, BlockDefinition::new("sandstone", Vec4::new(0.8, 0.7, 0.45, 1.0), 0.8).with_texture(BlockTextures::new(6, 5, 6))),
+            (BLOCK_GRAVEL, BlockDefinition::new("gravel", Vec4::new(0.55, 0.52, 0.5, 1.0), 0.6).with_texture(BlockTextures::all(7))),
+            (BLOCK_SNOW, BlockDefinition::new("snow", Vec4::new(0.95, 0.97, 1.0, 1.0), 0.2).with_texture(BlockTextures::all(8))),
+            (BLOCK_BEDROCK, BlockDefinition::new("bedrock", Vec4::new(0.15, 0.15, 0.15, 1.0), f32::INFINITY).with_texture(BlockTextures::all(9))),
+            (BLOCK_COAL_ORE, BlockDefinition::new("coal_ore", Vec4::new(0.2, 0.2, 0.2, 1.0), 3.0).with_texture(BlockTextures::all(10))),
+            (BLOCK_IRON_ORE, BlockDefinition::new("iron_ore", Vec4::new(0.75, 0.6, 0.5, 1.0), 3.0).with_texture(BlockTextures::all(11))),
+            (BLOCK_GOLD_ORE, BlockDefinition::new("gold_ore", Vec4::new(0.95, 0.8, 0.2, 1.0), 3.0).with_texture(BlockTextures::all(12))),
+            (BLOCK_DIAMOND_ORE, BlockDefinition::new("diamond_ore", Vec4::new(0.4, 0.9, 0.95, 1.0), 3.0).with_texture(BlockTextures::all(13))),
+            (BLOCK_WATER, BlockDefinition { solid: false, transparent: true, ..BlockDefinition::new("water", Vec4::new(0.2, 0.4, 0.9, 0.6), 0.0) }),
+            (BLOCK_LOG, BlockDefinition::new("log", Vec4::new(0.4, 0.27, 0.13, 1.0), 2.0).with_texture(BlockTextures::new(15, 14, 15))),
+            (BLOCK_LEAVES, BlockDefinition::new("leaves", Vec4::new(0.15, 0.45, 0.1, 1.0), 0.2).with_texture(BlockTextures::all(16))),
+            (BLOCK_TALL_GRASS, BlockDefinition { solid: false, ..BlockDefinition::new("tall_grass", Vec4::new(0.4, 0.75, 0.25, 1.0), 0.0).with_texture(BlockTextures::all(17)) }),
+            (BLOCK_CACTUS, BlockDefinition::new("cactus", Vec4::new(0.2, 0.55, 0.2, 1.0), 0.4).with_texture(BlockTextures::new(19, 18, 19))),
+        ];
+
+        for (id, definition) in blocks {
+            assert_eq!(registry.register(definition), id, "block constants and registry are out of order");
+        }
+
+        registry
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+use serde::Deserialize;
+
+use super::{BlockDefinition, BlockId, BlockRegistry, BlockTextures};
+
+// Path of the block definitions inside the assets folder
+pub const BLOCK_DEFINITIONS_PATH : &str = "default.blocks.ron";
+
+// One block of the definitions file, everything except the name and color can be left out
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BlockDefinitionData {
+    pub name: String,
+    pub visible: bool,
+    pub solid: bool,
+    pub transparent: bool,
+    pub color: [f32; 4],
+    // Tile of the texture atlas on the sides, and on the top and bottom unless they have their own tile
+    pub texture: Option<u32>,
+    pub top_texture: Option<u32>,
+    pub bottom_texture: Option<u32>,
+    pub hardness: f32,
+    pub light_emission: u8,
+}
+
+impl Default for BlockDefinitionData {
+    fn default() -> Self {
+        BlockDefinitionData {
+            name: String::new(),
+            visible: true,
+            solid: true,
+            transparent: false,
+            color: [1.0, 0.0, 1.0, 1.0],
+            texture: None,
+            top_texture: None,
+            bottom_texture: None,
+            hardness: 1.0,
+            light_emission: 0,
+        }
+    }
+}
+
+impl From<BlockDefinitionData> for BlockDefinition {
+    fn from(data: BlockDefinitionData) -> Self {
+        BlockDefinition {
+            name: data.name,
+            visible: data.visible,
+            solid: data.solid,
+            transparent: data.transparent,
+            color: Vec4::from_array(data.color),
+            texture: data.texture.map(|side| {
+                BlockTextures::new(data.top_texture.unwrap_or(side), side, data.bottom_texture.unwrap_or(side))
+            }),
+            hardness: data.hardness,
+            light_emission: data.light_emission,
+        }
+    }
+}
+
+// Block definitions file, so blocks can be added and tweaked without recompiling the game
+#[derive(Asset, TypePath, Deserialize, Clone, Debug)]
+pub struct BlockDefinitions {
+    pub blocks: Vec<BlockDefinitionData>,
+}
+
+impl BlockDefinitions {
+    // Build the registry of the file
+    // Blocks with the name of a built-in block replace it, all other blocks get added after the built-in ones
+    pub fn to_registry(&self) -> BlockRegistry {
+        let mut registry = BlockRegistry::default();
+
+        for block in &self.blocks {
+            registry.insert(block.clone().into());
+        }
+
+        registry
+    }
+}
+
+#[derive(Debug)]
+pub enum BlockDefinitionsError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+}
+
+impl fmt::Display for BlockDefinitionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockDefinitionsError::Io(error) => write!(f, "could not read block definitions: {}", error),
+            BlockDefinitionsError::Ron(error) => write!(f, "could not parse block definitions: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for BlockDefinitionsError {}
+
+impl From<std::io::Error> for BlockDefinitionsError {
+    fn from(error: std::io::Error) -> Self {
+        BlockDefinitionsError::Io(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for BlockDefinitionsError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        BlockDefinitionsError::Ron(error)
+    }
+}
+
+#[derive(Default)]
+pub struct BlockDefinitionsLoader;
+
+impl AssetLoader for BlockDefinitionsLoader {
+    type Asset = BlockDefinitions;
+    type Settings = ();
+    type Error = BlockDefinitionsError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<BlockDefinitions, BlockDefinitionsError>> {
+        Box::pin(async move {
+            let mut bytes: Vec<u8> = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+
+            Ok(ron::de::from_bytes::<BlockDefinitions>(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["blocks.ron"]
+    }
+}
+
+// Keeps the block definitions loaded, so the file gets watched for changes
+#[derive(Resource)]
+pub struct BlockDefinitionsHandle(pub Handle<BlockDefinitions>);
+
+// Sent when the block registry changed, with the blocks that look or behave differently now
+#[derive(Event)]
+pub struct BlockDefinitionsChanged {
+    pub blocks: Vec<BlockId>,
+}
+
+pub(super) fn load_block_definitions(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(BlockDefinitionsHandle(asset_server.load(BLOCK_DEFINITIONS_PATH)));
+}
+
+// Rebuild the block registry every time the definitions file is loaded or changed
+pub(super) fn update_block_registry(
+    mut asset_events: EventReader<AssetEvent<BlockDefinitions>>,
+    mut changed_events: EventWriter<BlockDefinitionsChanged>,
+    definitions: Res<Assets<BlockDefinitions>>,
+    handle: Res<BlockDefinitionsHandle>,
+    mut registry: ResMut<BlockRegistry>,
+) {
+    for event in asset_events.read() {
+        let (AssetEvent::Added { id } | AssetEvent::Modified { id }) = event else { continue };
+
+        if *id != handle.0.id() {
+            continue;
+        }
+
+        let Some(definitions) = definitions.get(*id) else { continue };
+        let new_registry = definitions.to_registry();
+
+        let count : usize = registry.len().max(new_registry.len());
+        let blocks: Vec<BlockId> = (0..count as u16)
+            .map(BlockId)
+            .filter(|block| registry.get(*block) != new_registry.get(*block))
+            .collect();
+
+        *registry = new_registry;
+
+        if !blocks.is_empty() {
+            changed_events.send(BlockDefinitionsChanged { blocks });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_definitions_file_matches_built_in_blocks() {
+        let definitions: BlockDefinitions = ron::from_str(include_str!("../../assets/default.blocks.ron")).unwrap();
+        let registry = definitions.to_registry();
+        let built_in = BlockRegistry::default();
+
+        assert_eq!(registry.len(), built_in.len());
+
+        for id in 0..built_in.len() as u16 {
+            assert_eq!(registry.get(BlockId(id)), built_in.get(BlockId(id)));
+        }
+    }
+}
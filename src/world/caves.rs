@@ -0,0 +1,106 @@
+use super::{NoiseStream, NoiseType, TerrainNoise, WorldGenSettings};
+
+// Cave carving of the density terrain
+// Cheese caves are big open rooms where one noise is high,
+// spaghetti caves are long thin tunnels where two noises are close to zero at the same time
+#[derive(Clone)]
+pub struct Caves {
+    cheese: TerrainNoise,
+    spaghetti: [TerrainNoise; 2],
+    cheese_threshold: f64,
+    spaghetti_width: f64,
+}
+
+impl Caves {
+    pub fn new(settings: &WorldGenSettings) -> Self {
+        // Own noise streams, so the caves don't follow the terrain noise
+        let cheese = TerrainNoise::new(settings.seed, NoiseStream::Cheese, 2, settings.cave_scale, 2.0, 0.5, NoiseType::Standard);
+        let spaghetti = [
+            TerrainNoise::new(settings.seed, NoiseStream::Spaghetti(0), 1, settings.cave_scale * 2.0, 2.0, 0.5, NoiseType::Standard),
+            TerrainNoise::new(settings.seed, NoiseStream::Spaghetti(1), 1, settings.cave_scale * 2.0, 2.0, 0.5, NoiseType::Standard),
+        ];
+
+        Caves {
+            cheese,
+            spaghetti,
+            cheese_threshold: settings.cheese_threshold,
+            spaghetti_width: settings.spaghetti_width,
+        }
+    }
+
+    // Check if the block at x, y, z gets carved out
+    pub fn is_cave(&self, x: i32, y: i32, z: i32) -> bool {
+        let (x, y, z) = (x as f64, y as f64, z as f64);
+
+        // Stretch the rooms horizontally, so they are wider than high
+        if self.cheese.get_3d(x, y * 2.0, z) > self.cheese_threshold {
+            return true;
+        }
+
+        self.spaghetti[0].get_3d(x, y, z).abs() < self.spaghetti_width
+            && self.spaghetti[1].get_3d(x, y, z).abs() < self.spaghetti_width
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::*;
+    use crate::world::{BlockId, NoiseTerrain, TerrainGenerator, TerrainMode, BLOCK_AIR, BLOCK_WATER};
+
+    // Density terrain without water, so the caves are filled with air
+    // The rivers would carve their beds down to below the sea level, so they are gone as well
+    fn test_settings() -> WorldGenSettings {
+        WorldGenSettings { terrain_mode: TerrainMode::Density, sea_level: -1000, river_width: 0.0, bedrock_level: None, ..default() }
+    }
+
+    // Box far below the ground level of 100, where the terrain is solid everywhere except for the caves
+    fn underground(settings: &WorldGenSettings) -> Vec<BlockId> {
+        NoiseTerrain::new(settings).generate(IVec3::new(0, 0, 0), IVec3::new(64, 32, 64))
+    }
+
+    #[test]
+    fn caves_carve_air_below_the_surface() {
+        let blocks = underground(&test_settings());
+
+        assert!(blocks.contains(&BLOCK_AIR));
+    }
+
+    #[test]
+    fn without_caves_the_underground_is_solid() {
+        // The noise never gets above 1, and nothing is closer to zero than 0
+        let settings = WorldGenSettings { cheese_threshold: 1.0, spaghetti_width: 0.0, ..test_settings() };
+        let caves = Caves::new(&settings);
+
+        assert!(underground(&settings).iter().all(|block| *block != BLOCK_AIR && *block != BLOCK_WATER));
+        assert!((0..64).all(|x| (0..32).all(|y| !caves.is_cave(x, y, 0))));
+    }
+
+    #[test]
+    fn terrain_mode_selects_heightmap_or_density_terrain() {
+        let size = IVec3::new(8, 32, 8);
+        let position = IVec3::new(16, 0, -16);
+
+        for terrain_mode in [TerrainMode::Heightmap, TerrainMode::Density] {
+            let settings = WorldGenSettings { terrain_mode, ..test_settings() };
+            let terrain = NoiseTerrain::new(&settings);
+            let blocks = terrain.generate(position, size);
+
+            for (i, block) in blocks.iter().enumerate() {
+                let i : i32 = i as i32;
+                let (x, y, z) = (position.x + i % size.x, position.y + i / (size.x * size.z), position.z + (i / size.x) % size.z);
+
+                let expected = match terrain_mode {
+                    TerrainMode::Heightmap => terrain.get_block(x, y, z),
+                    TerrainMode::Density => terrain.get_block_density(x, y, z),
+                };
+
+                assert_eq!(*block, expected, "{:?} {} {} {}", terrain_mode, x, y, z);
+            }
+
+            // Only the density terrain has caves
+            assert_eq!(blocks.contains(&BLOCK_AIR), terrain_mode == TerrainMode::Density, "{:?}", terrain_mode);
+        }
+    }
+}
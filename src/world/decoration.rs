@@ -0,0 +1,205 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::{Biome, BiomeMap, BlockId, BlockRegistry, Chunk, WorldGenSettings, SECTION_HEIGHT};
+use super::{BLOCK_AIR, BLOCK_CACTUS, BLOCK_GRASS, BLOCK_LEAVES, BLOCK_LOG, BLOCK_SAND, BLOCK_SNOW, BLOCK_TALL_GRASS, BLOCK_WATER};
+
+// Number of spots per column of a section where the decoration pass tries to place a feature
+const ATTEMPTS_PER_BLOCK : f32 = 1.0 / 8.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Feature {
+    Tree,
+    Bush,
+    TallGrass,
+    Cactus,
+}
+
+// Features of every biome and the chance that one gets placed at a spot
+fn biome_features(biome: Biome) -> &'static [(Feature, f32)] {
+    match biome {
+        Biome::Plains => &[(Feature::Tree, 0.04), (Feature::Bush, 0.05), (Feature::TallGrass, 0.4)],
+        Biome::Desert => &[(Feature::Cactus, 0.03)],
+        Biome::Mountains => &[(Feature::Bush, 0.02)],
+        Biome::Tundra => &[(Feature::Tree, 0.02)],
+        Biome::Ocean => &[],
+    }
+}
+
+// Decorations never replace terrain, and if two decorations want the same block the one with the higher priority wins
+// That way the result is the same, no matter in which order the sections get generated
+fn write_priority(block_type: BlockId) -> u8 {
+    match block_type {
+        BLOCK_AIR | BLOCK_WATER => 0,
+        BLOCK_TALL_GRASS => 1,
+        BLOCK_LEAVES => 2,
+        BLOCK_CACTUS => 3,
+        BLOCK_LOG => 4,
+        _ => u8::MAX,
+    }
+}
+
+// Writes of decorations that reach into other sections, sorted by the section they reach into
+// They are kept as long as the section that made them is loaded, so a section which gets unloaded and generated again gets them back
+#[derive(Resource, Default)]
+pub struct PendingWrites {
+    // Position of the section that made the write, world position and block type of every write
+    writes: HashMap<IVec3, Vec<(IVec3, IVec3, BlockId)>>,
+}
+
+impl PendingWrites {
+    pub(super) fn push(&mut self, section_position: IVec3, source_position: IVec3, world_position: IVec3, block_type: BlockId) {
+        self.writes.entry(section_position).or_default().push((source_position, world_position, block_type));
+    }
+
+    // Apply all writes into the section at the world position
+    pub(super) fn apply(&self, chunk: &mut Chunk, section_position: IVec3, registry: &BlockRegistry) {
+        if let Some(writes) = self.writes.get(&section_position) {
+            for (_, world_position, block_type) in writes {
+                place_block(chunk, *world_position, *block_type, registry);
+            }
+        }
+    }
+
+    // Forget the writes made by the section at the position
+    pub(super) fn remove_section(&mut self, section_position: IVec3) {
+        for writes in self.writes.values_mut() {
+            writes.retain(|(source_position, _, _)| *source_position != section_position);
+        }
+
+        self.writes.retain(|_, writes| !writes.is_empty());
+    }
+
+    // Forget the writes made by the sections of the chunk at the position
+    pub(super) fn remove_column(&mut self, chunk_position: IVec2) {
+        for writes in self.writes.values_mut() {
+            writes.retain(|(source_position, _, _)| IVec2::new(source_position.x, source_position.z) != chunk_position);
+        }
+
+        self.writes.retain(|_, writes| !writes.is_empty());
+    }
+}
+
+// Write a decoration block into the chunk, if it has a higher priority than the block already there
+pub(super) fn place_block(chunk: &mut Chunk, world_position: IVec3, block_type: BlockId, registry: &BlockRegistry) {
+    let chunk_position : IVec2 = chunk.position();
+    let local = world_position - IVec3::new(chunk_position.x, 0, chunk_position.y);
+
+    let Some(current) = chunk.get_block(local) else { return };
+
+    if write_priority(block_type) > write_priority(current) {
+        chunk.set_block(local, block_type, registry);
+    }
+}
+
+// Position of the lowest corner of the section which contains the world position
+pub(super) fn section_position_of(world_position: IVec3, settings: &WorldGenSettings) -> IVec3 {
+    let size = IVec3::new(settings.chunk_width, SECTION_HEIGHT, settings.chunk_width);
+
+    world_position.div_euclid(size) * size
+}
+
+// Every section gets its own random generator, seeded by the world seed and the section position
+fn section_rng(position: IVec3, settings: &WorldGenSettings) -> StdRng {
+    let seed : u64 = (settings.seed as u64)
+        ^ (position.x as i64 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (position.y as i64 as u64).wrapping_mul(0x1656_67B1_9E37_79F9)
+        ^ (position.z as i64 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+
+    StdRng::seed_from_u64(seed)
+}
+
+// Find the highest solid block of the column in the section with a non solid block above it
+// The blocks have one layer more than the section, so the top layer of the section knows what is above it
+fn surface_at(blocks: &[BlockId], size: IVec3, registry: &BlockRegistry, x: i32, z: i32) -> Option<(i32, BlockId)> {
+    let block_at = |y: i32| blocks[(x + z * size.x + y * size.x * size.z) as usize];
+
+    (0..size.y)
+        .rev()
+        .find(|y| registry.get(block_at(*y)).solid && !registry.get(block_at(y + 1)).solid)
+        .map(|y| (y, block_at(y)))
+}
+
+// Run the decoration pass on the blocks of a freshly generated section with its lowest corner at the world position
+// Returns all blocks of the placed features in world coordinates, including the ones outside of the section
+pub(super) fn decorate(blocks: &[BlockId], position: IVec3, size: IVec3, biomes: &BiomeMap, registry: &BlockRegistry, settings: &WorldGenSettings) -> Vec<(IVec3, BlockId)> {
+    let mut rng = section_rng(position, settings);
+    let mut writes : Vec<(IVec3, BlockId)> = Vec::new();
+
+    let attempts : i32 = ((size.x * size.z) as f32 * ATTEMPTS_PER_BLOCK) as i32;
+
+    for _ in 0..attempts {
+        // Always roll all random values, so every attempt uses the same amount of randomness
+        let x : i32 = rng.gen_range(0..size.x);
+        let z : i32 = rng.gen_range(0..size.z);
+        let roll : f32 = rng.gen();
+        let feature_size : i32 = rng.gen_range(0..3);
+
+        let Some((surface_y, surface_block)) = surface_at(blocks, size, registry, x, z) else { continue };
+
+        let base = position + IVec3::new(x, surface_y + 1, z);
+
+        // Nothing grows under water
+        if base.y < settings.sea_level {
+            continue;
+        }
+
+        let mut chance : f32 = 0.0;
+        let feature = biome_features(biomes.biome_at(base.x, base.z))
+            .iter()
+            .find(|(_, feature_chance)| {
+                chance += feature_chance;
+                roll < chance
+            })
+            .map(|(feature, _)| *feature);
+
+        match feature {
+            Some(Feature::Tree) if surface_block == BLOCK_GRASS || surface_block == BLOCK_SNOW => place_tree(&mut writes, base, 4 + feature_size),
+            Some(Feature::Bush) if surface_block != BLOCK_SAND => place_bush(&mut writes, base, feature_size),
+            Some(Feature::TallGrass) if surface_block == BLOCK_GRASS => writes.push((base, BLOCK_TALL_GRASS)),
+            Some(Feature::Cactus) if surface_block == BLOCK_SAND => {
+                for y in 0..=feature_size {
+                    writes.push((base + IVec3::Y * y, BLOCK_CACTUS));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    writes
+}
+
+// Trunk with a round crown, the crown is wide enough to reach into the neighbor sections
+fn place_tree(writes: &mut Vec<(IVec3, BlockId)>, base: IVec3, height: i32) {
+    for y in 0..height {
+        writes.push((base + IVec3::Y * y, BLOCK_LOG));
+    }
+
+    let top = base + IVec3::Y * height;
+
+    for y in -2..=1 {
+        let radius : i32 = if y < 0 { 2 } else { 1 };
+
+        for x in -radius..=radius {
+            for z in -radius..=radius {
+                // Cut off the corners of the crown
+                if x.abs() == radius && z.abs() == radius && radius > 1 {
+                    continue;
+                }
+
+                writes.push((top + IVec3::new(x, y, z), BLOCK_LEAVES));
+            }
+        }
+    }
+}
+
+// Small pile of leaves
+fn place_bush(writes: &mut Vec<(IVec3, BlockId)>, base: IVec3, size: i32) {
+    writes.push((base, BLOCK_LEAVES));
+
+    for offset in [IVec3::X, IVec3::Z, IVec3::NEG_X, IVec3::NEG_Z].iter().take(size as usize) {
+        writes.push((base + *offset, BLOCK_LEAVES));
+    }
+}
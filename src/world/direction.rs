@@ -0,0 +1,56 @@
+use bevy::prelude::*;
+
+// Directions to the six neighbors of a block or section
+// East and west are along x, south and north along z, up and down along y
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Direction {
+    East,
+    West,
+    Up,
+    Down,
+    South,
+    North,
+}
+
+// Same order as the faces of a cube in FACE_CORNERS
+pub const DIRECTIONS : [Direction; 6] = [Direction::East, Direction::West, Direction::Up, Direction::Down, Direction::South, Direction::North];
+
+// Directions to the neighbor chunks, chunks are columns so they have no neighbors above or below
+pub const HORIZONTAL_DIRECTIONS : [Direction; 4] = [Direction::East, Direction::West, Direction::South, Direction::North];
+
+impl Direction {
+    // One block step in this direction
+    pub fn offset(&self) -> IVec3 {
+        match self {
+            Direction::East => IVec3::X,
+            Direction::West => IVec3::NEG_X,
+            Direction::Up => IVec3::Y,
+            Direction::Down => IVec3::NEG_Y,
+            Direction::South => IVec3::Z,
+            Direction::North => IVec3::NEG_Z,
+        }
+    }
+
+    // Index of the axis the direction goes along, 0 for x, 1 for y and 2 for z
+    pub fn axis(&self) -> usize {
+        match self {
+            Direction::East | Direction::West => 0,
+            Direction::Up | Direction::Down => 1,
+            Direction::South | Direction::North => 2,
+        }
+    }
+
+    // Step on the x, z grid of the chunks, zero for up and down
+    pub fn chunk_offset(&self) -> IVec2 {
+        let offset : IVec3 = self.offset();
+
+        IVec2::new(offset.x, offset.z)
+    }
+}
+
+// Offsets to the 26 blocks or sections around one, the diagonal ones included
+pub fn neighbor_offsets() -> impl Iterator<Item = IVec3> {
+    (-1..=1)
+        .flat_map(|y| (-1..=1).flat_map(move |z| (-1..=1).map(move |x| IVec3::new(x, y, z))))
+        .filter(|offset| *offset != IVec3::ZERO)
+}
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+
+use super::heightmap::{HeightmapError, HeightmapSettings, HeightmapTerrain};
+use super::noise_terrain::NoiseTerrain;
+use super::{BlockId, WorldGenSettings};
+use super::{BLOCK_AIR, BLOCK_BEDROCK, BLOCK_DIRT, BLOCK_GRASS, BLOCK_SAND, BLOCK_SANDSTONE, BLOCK_STONE, BLOCK_WATER};
+
+// Source of the terrain of a world
+// Fills the blocks of the box with its lowest corner at the world position, x first, then z, then y
+// The box can be at any height, so the world has no lowest or highest block
+pub trait TerrainGenerator: Send + Sync {
+    fn generate(&self, position: IVec3, size: IVec3) -> Vec<BlockId>;
+
+    // Check if the decoration pass (trees, grass, ...) should run on the chunks of this generator
+    fn has_decorations(&self) -> bool {
+        false
+    }
+}
+
+// The generator used by the world, can be replaced after adding the WorldPlugin to use a custom generator
+// Shared with the generation tasks, which run on other threads
+#[derive(Resource)]
+pub struct WorldGenerator(pub Arc<dyn TerrainGenerator>);
+
+// Built-in generators, selectable via the WorldGenSettings
+#[derive(Clone, Debug)]
+pub enum GeneratorKind {
+    Noise,
+    Flat(Vec<FlatLayer>),
+    Void,
+    Heightmap(HeightmapSettings),
+}
+
+impl GeneratorKind {
+    // Only fails if the heightmap image can't be loaded
+    pub fn build(&self, settings: &WorldGenSettings) -> Result<Box<dyn TerrainGenerator>, HeightmapError> {
+        Ok(match self {
+            GeneratorKind::Noise => Box::new(NoiseTerrain::new(settings)),
+            GeneratorKind::Flat(layers) => Box::new(FlatTerrain::new(layers.clone())),
+            GeneratorKind::Void => Box::new(VoidTerrain),
+            GeneratorKind::Heightmap(heightmap) => Box::new(HeightmapTerrain::load(heightmap, settings)?),
+        })
+    }
+
+    // Build the generator, a missing or broken heightmap gets logged instead of stopping the game
+    // Without the image every column has the border height, without a border height the world is empty
+    pub fn build_or_fallback(&self, settings: &WorldGenSettings) -> Box<dyn TerrainGenerator> {
+        self.build(settings).unwrap_or_else(|error| {
+            error!("could not build the {:?} generator: {}", self, error);
+
+            match self {
+                GeneratorKind::Heightmap(heightmap) if heightmap.border_height.is_some() => {
+                    Box::new(HeightmapTerrain::from_values(0, 0, &[], heightmap, settings))
+                }
+                _ => Box::new(VoidTerrain),
+            }
+        })
+    }
+}
+
+// Layer of a flat world, listed from the bottom up
+#[derive(Clone, Copy, Debug)]
+pub struct FlatLayer {
+    pub block_type: BlockId,
+    pub height: i32,
+}
+
+impl FlatLayer {
+    pub fn new(block_type: BlockId, height: i32) -> Self {
+        FlatLayer { block_type, height }
+    }
+}
+
+// Every column of the world is the same stack of layers, everything above is air
+pub struct FlatTerrain {
+    // Block type of every y, precomputed from the layers
+    column: Vec<BlockId>,
+}
+
+impl FlatTerrain {
+    pub fn new(layers: Vec<FlatLayer>) -> Self {
+        let column: Vec<BlockId> = layers
+            .iter()
+            .flat_map(|layer| std::iter::repeat_n(layer.block_type, layer.height.max(0) as usize))
+            .collect();
+
+        FlatTerrain { column }
+    }
+
+    // Superflat presets
+    pub fn classic() -> Vec<FlatLayer> {
+        vec![
+            FlatLayer::new(BLOCK_BEDROCK, 1),
+            FlatLayer::new(BLOCK_DIRT, 2),
+            FlatLayer::new(BLOCK_GRASS, 1),
+        ]
+    }
+
+    pub fn desert() -> Vec<FlatLayer> {
+        vec![
+            FlatLayer::new(BLOCK_BEDROCK, 1),
+            FlatLayer::new(BLOCK_STONE, 3),
+            FlatLayer::new(BLOCK_SANDSTONE, 52),
+            FlatLayer::new(BLOCK_SAND, 8),
+        ]
+    }
+
+    pub fn water_world() -> Vec<FlatLayer> {
+        vec![
+            FlatLayer::new(BLOCK_BEDROCK, 1),
+            FlatLayer::new(BLOCK_STONE, 5),
+            FlatLayer::new(BLOCK_SAND, 5),
+            FlatLayer::new(BLOCK_WATER, 90),
+        ]
+    }
+}
+
+impl TerrainGenerator for FlatTerrain {
+    fn generate(&self, position: IVec3, size: IVec3) -> Vec<BlockId> {
+        let layer_size : usize = (size.x * size.z) as usize;
+        let mut blocks: Vec<BlockId> = Vec::with_capacity(layer_size * size.y as usize);
+
+        for y in position.y..position.y + size.y {
+            // Below the first layer is only air
+            let block_type : BlockId = usize::try_from(y).ok().and_then(|y| self.column.get(y)).copied().unwrap_or(BLOCK_AIR);
+            blocks.extend(std::iter::repeat_n(block_type, layer_size));
+        }
+
+        blocks
+    }
+}
+
+// Empty world, only air
+pub struct VoidTerrain;
+
+impl TerrainGenerator for VoidTerrain {
+    fn generate(&self, _position: IVec3, size: IVec3) -> Vec<BlockId> {
+        vec![BLOCK_AIR; (size.x * size.y * size.z) as usize]
+    }
+}
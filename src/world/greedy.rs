@@ -0,0 +1,66 @@
+use super::BlockId;
+
+// Everything that decides how a face looks, only faces which look the same can be merged
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FaceKey {
+    pub block: BlockId,
+    // Ambient occlusion of the four corners of the face
+    pub ao: [u8; 4],
+}
+
+// Rectangle of merged faces in a slice of a section
+// u and v are the two axes of the slice, the rectangle covers u..u + width and v..v + height
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Quad {
+    pub u: i32,
+    pub v: i32,
+    pub width: i32,
+    pub height: i32,
+    pub key: FaceKey,
+}
+
+// Merge the visible faces of a slice into rectangles of faces which look the same
+// The mask has width * height cells, row by row along u, with the key of every visible face and None for hidden ones
+// Every face is taken out of the mask as soon as it is merged, so each cell only gets looked at a few times
+pub fn greedy_quads(mask: &mut [Option<FaceKey>], width: i32, height: i32) -> Vec<Quad> {
+    let mut quads: Vec<Quad> = Vec::new();
+
+    for v in 0..height {
+        let mut u : i32 = 0;
+
+        while u < width {
+            let Some(key) = mask[(u + v * width) as usize] else {
+                u += 1;
+                continue;
+            };
+
+            // Grow the rectangle along u as far as the faces look the same
+            let mut quad_width : i32 = 1;
+
+            while u + quad_width < width && mask[(u + quad_width + v * width) as usize] == Some(key) {
+                quad_width += 1;
+            }
+
+            // Then grow it along v as long as the whole next row matches
+            let mut quad_height : i32 = 1;
+
+            while v + quad_height < height
+                && (u..u + quad_width).all(|row_u| mask[(row_u + (v + quad_height) * width) as usize] == Some(key))
+            {
+                quad_height += 1;
+            }
+
+            for quad_v in v..v + quad_height {
+                for quad_u in u..u + quad_width {
+                    mask[(quad_u + quad_v * width) as usize] = None;
+                }
+            }
+
+            quads.push(Quad { u, v, width: quad_width, height: quad_height, key });
+
+            u += quad_width;
+        }
+    }
+
+    quads
+}
@@ -0,0 +1,318 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use super::generator::TerrainGenerator;
+use super::{BlockId, WorldGenSettings};
+use super::{BLOCK_AIR, BLOCK_BEDROCK, BLOCK_DIRT, BLOCK_GRASS, BLOCK_STONE, BLOCK_WATER};
+
+// Configuration of a terrain loaded from a grayscale PNG
+// Black pixels are at offset, white pixels at offset + vertical_scale
+#[derive(Clone, Debug)]
+pub struct HeightmapSettings {
+    pub path: PathBuf,
+    pub vertical_scale: f64,
+    pub offset: i32,
+    // World x, z of the top left pixel
+    pub origin: IVec2,
+    // Height of the columns outside of the image, None leaves them empty
+    pub border_height: Option<i32>,
+}
+
+impl Default for HeightmapSettings {
+    fn default() -> Self {
+        HeightmapSettings {
+            path: PathBuf::from("assets/heightmap.png"),
+            vertical_scale: 64.0,
+            offset: 64,
+            origin: IVec2::ZERO,
+            border_height: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HeightmapError {
+    Io(std::io::Error),
+    Decoding(png::DecodingError),
+    // Only grayscale images with 8 or 16 bits are supported
+    UnsupportedFormat(png::ColorType, png::BitDepth),
+}
+
+impl fmt::Display for HeightmapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeightmapError::Io(error) => write!(f, "could not read heightmap: {}", error),
+            HeightmapError::Decoding(error) => write!(f, "could not decode heightmap: {}", error),
+            HeightmapError::UnsupportedFormat(color_type, bit_depth) => {
+                write!(f, "heightmap has to be an 8 or 16 bit grayscale PNG, found {:?} with {:?}", color_type, bit_depth)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HeightmapError {}
+
+impl From<std::io::Error> for HeightmapError {
+    fn from(error: std::io::Error) -> Self {
+        HeightmapError::Io(error)
+    }
+}
+
+impl From<png::DecodingError> for HeightmapError {
+    fn from(error: png::DecodingError) -> Self {
+        HeightmapError::Decoding(error)
+    }
+}
+
+// Terrain with the column heights of a heightmap image, so terrain can be authored in external tools
+pub struct HeightmapTerrain {
+    width: i32,
+    depth: i32,
+    // Height of every pixel, row by row
+    heights: Vec<i32>,
+    origin: IVec2,
+    border_height: Option<i32>,
+    sea_level: i32,
+    bedrock_level: Option<i32>,
+}
+
+impl HeightmapTerrain {
+    pub fn load(heightmap: &HeightmapSettings, settings: &WorldGenSettings) -> Result<Self, HeightmapError> {
+        HeightmapTerrain::decode(File::open(&heightmap.path)?, heightmap, settings)
+    }
+
+    // Build the terrain from a PNG read from anywhere, the path of the heightmap settings is ignored
+    pub fn decode(image: impl Read, heightmap: &HeightmapSettings, settings: &WorldGenSettings) -> Result<Self, HeightmapError> {
+        let mut decoder = png::Decoder::new(image);
+        // Expand 1, 2 and 4 bit images to 8 bit, 16 bit images stay as they are
+        decoder.set_transformations(png::Transformations::EXPAND);
+
+        let mut reader = decoder.read_info()?;
+        let mut buffer: Vec<u8> = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+
+        let channels : usize = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            color_type => return Err(HeightmapError::UnsupportedFormat(color_type, info.bit_depth)),
+        };
+
+        let mut values: Vec<f64> = Vec::with_capacity((info.width * info.height) as usize);
+
+        for row in buffer.chunks(info.line_size).take(info.height as usize) {
+            for x in 0..info.width as usize {
+                // Only the gray channel is used, alpha gets ignored
+                let value : f64 = match info.bit_depth {
+                    png::BitDepth::Eight => row[x * channels] as f64 / u8::MAX as f64,
+                    png::BitDepth::Sixteen => {
+                        let i : usize = x * channels * 2;
+                        u16::from_be_bytes([row[i], row[i + 1]]) as f64 / u16::MAX as f64
+                    }
+                    bit_depth => return Err(HeightmapError::UnsupportedFormat(info.color_type, bit_depth)),
+                };
+
+                values.push(value);
+            }
+        }
+
+        Ok(HeightmapTerrain::from_values(info.width as i32, info.height as i32, &values, heightmap, settings))
+    }
+
+    // Build the terrain from pixel values in the range 0..1, row by row
+    pub fn from_values(width: i32, depth: i32, values: &[f64], heightmap: &HeightmapSettings, settings: &WorldGenSettings) -> Self {
+        let heights: Vec<i32> = values
+            .iter()
+            .map(|value| heightmap.offset + (value * heightmap.vertical_scale).round() as i32)
+            .collect();
+
+        HeightmapTerrain {
+            width,
+            depth,
+            heights,
+            origin: heightmap.origin,
+            border_height: heightmap.border_height,
+            sea_level: settings.sea_level,
+            bedrock_level: settings.bedrock_level,
+        }
+    }
+
+    // Get the height of the column at world x, z, None if it is outside of the image and there is no border
+    pub fn height_at(&self, x: i32, z: i32) -> Option<i32> {
+        let pixel_x : i32 = x - self.origin.x;
+        let pixel_z : i32 = z - self.origin.y;
+
+        if pixel_x < 0 || pixel_z < 0 || pixel_x >= self.width || pixel_z >= self.depth {
+            return self.border_height;
+        }
+
+        Some(self.heights[(pixel_x + pixel_z * self.width) as usize])
+    }
+
+    fn get_block(&self, y: i32, height: Option<i32>) -> BlockId {
+        let Some(height) = height else { return BLOCK_AIR };
+
+        if self.bedrock_level.is_some_and(|bedrock_level| y <= bedrock_level) {
+            BLOCK_BEDROCK
+        } else if y >= height {
+            if y < self.sea_level { BLOCK_WATER } else { BLOCK_AIR }
+        } else if y == height - 1 {
+            BLOCK_GRASS
+        } else if y >= height - 4 {
+            BLOCK_DIRT
+        } else {
+            BLOCK_STONE
+        }
+    }
+}
+
+impl TerrainGenerator for HeightmapTerrain {
+    fn generate(&self, position: IVec3, size: IVec3) -> Vec<BlockId> {
+        // Look up the height of every column only once
+        let heights: Vec<Option<i32>> = (0..size.x * size.z)
+            .map(|i| self.height_at(position.x + i % size.x, position.z + i / size.x))
+            .collect();
+
+        let mut blocks: Vec<BlockId> = Vec::with_capacity((size.x * size.y * size.z) as usize);
+
+        for y in position.y..position.y + size.y {
+            for height in &heights {
+                blocks.push(self.get_block(y, *height));
+            }
+        }
+
+        blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::GeneratorKind;
+
+    fn test_settings() -> WorldGenSettings {
+        WorldGenSettings { sea_level: 0, bedrock_level: None, ..default() }
+    }
+
+    // 2 x 2 image at x 5, z -3
+    fn test_heightmap(border_height: Option<i32>) -> HeightmapSettings {
+        HeightmapSettings {
+            path: PathBuf::from("does/not/exist.png"),
+            vertical_scale: 64.0,
+            offset: 10,
+            origin: IVec2::new(5, -3),
+            border_height,
+        }
+    }
+
+    // Encode a tiny image in memory, so the tests don't need image files
+    fn encode(width: u32, height: u32, color_type: png::ColorType, bit_depth: png::BitDepth, data: &[u8]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(color_type);
+        encoder.set_depth(bit_depth);
+        encoder.write_header().unwrap().write_image_data(data).unwrap();
+
+        bytes
+    }
+
+    // Blocks of the column at x, z from y 0 up to 128
+    fn column(generator: &dyn TerrainGenerator, x: i32, z: i32) -> Vec<BlockId> {
+        generator.generate(IVec3::new(x, 0, z), IVec3::new(1, 128, 1))
+    }
+
+    #[test]
+    fn pixel_values_are_scaled_and_offset() {
+        let terrain = HeightmapTerrain::from_values(2, 2, &[0.0, 0.5, 1.0, 0.25], &test_heightmap(None), &test_settings());
+
+        assert_eq!(terrain.height_at(5, -3), Some(10));
+        assert_eq!(terrain.height_at(6, -3), Some(42));
+        assert_eq!(terrain.height_at(5, -2), Some(74));
+        assert_eq!(terrain.height_at(6, -2), Some(26));
+
+        let heightmap = HeightmapSettings { vertical_scale: 10.0, offset: -4, ..test_heightmap(None) };
+        let terrain = HeightmapTerrain::from_values(2, 2, &[0.0, 0.5, 1.0, 0.25], &heightmap, &test_settings());
+
+        assert_eq!(terrain.height_at(5, -3), Some(-4));
+        assert_eq!(terrain.height_at(6, -3), Some(1));
+        assert_eq!(terrain.height_at(5, -2), Some(6));
+        assert_eq!(terrain.height_at(6, -2), Some(-1));
+    }
+
+    #[test]
+    fn columns_outside_of_the_image_get_the_border_height() {
+        let values : [f64; 4] = [0.5; 4];
+        let outside : [(i32, i32); 4] = [(4, -3), (7, -3), (5, -4), (5, -1)];
+
+        let empty = HeightmapTerrain::from_values(2, 2, &values, &test_heightmap(None), &test_settings());
+        let bordered = HeightmapTerrain::from_values(2, 2, &values, &test_heightmap(Some(20)), &test_settings());
+
+        for (x, z) in outside {
+            assert_eq!(empty.height_at(x, z), None);
+            assert!(column(&empty, x, z).iter().all(|block| *block == BLOCK_AIR));
+
+            assert_eq!(bordered.height_at(x, z), Some(20));
+            assert_eq!(column(&bordered, x, z)[19], BLOCK_GRASS);
+            assert_eq!(column(&bordered, x, z)[20], BLOCK_AIR);
+        }
+
+        // Inside of the image the border doesn't matter
+        assert_eq!(bordered.height_at(6, -2), Some(42));
+        assert_eq!(column(&bordered, 6, -2)[41], BLOCK_GRASS);
+    }
+
+    #[test]
+    fn eight_and_sixteen_bit_images_are_decoded() {
+        let heightmap = HeightmapSettings { vertical_scale: 65535.0, offset: 0, ..test_heightmap(None) };
+
+        let image = encode(2, 2, png::ColorType::Grayscale, png::BitDepth::Eight, &[0, 1, 128, 255]);
+        let terrain = HeightmapTerrain::decode(image.as_slice(), &heightmap, &test_settings()).unwrap();
+
+        assert_eq!(terrain.height_at(5, -3), Some(0));
+        assert_eq!(terrain.height_at(6, -3), Some(257));
+        assert_eq!(terrain.height_at(5, -2), Some(128 * 257));
+        assert_eq!(terrain.height_at(6, -2), Some(65535));
+
+        // 16 bit images are big endian and keep their full precision
+        let image = encode(2, 2, png::ColorType::Grayscale, png::BitDepth::Sixteen, &[0, 0, 0, 1, 0x12, 0x34, 0xFF, 0xFF]);
+        let terrain = HeightmapTerrain::decode(image.as_slice(), &heightmap, &test_settings()).unwrap();
+
+        assert_eq!(terrain.height_at(5, -3), Some(0));
+        assert_eq!(terrain.height_at(6, -3), Some(1));
+        assert_eq!(terrain.height_at(5, -2), Some(0x1234));
+        assert_eq!(terrain.height_at(6, -2), Some(65535));
+
+        // The alpha channel is ignored
+        let image = encode(2, 1, png::ColorType::GrayscaleAlpha, png::BitDepth::Eight, &[255, 0, 0, 255]);
+        let terrain = HeightmapTerrain::decode(image.as_slice(), &heightmap, &test_settings()).unwrap();
+
+        assert_eq!(terrain.height_at(5, -3), Some(65535));
+        assert_eq!(terrain.height_at(6, -3), Some(0));
+    }
+
+    #[test]
+    fn color_images_are_rejected() {
+        let image = encode(1, 1, png::ColorType::Rgb, png::BitDepth::Eight, &[1, 2, 3]);
+        let result = HeightmapTerrain::decode(image.as_slice(), &test_heightmap(None), &test_settings());
+
+        assert!(matches!(result, Err(HeightmapError::UnsupportedFormat(png::ColorType::Rgb, png::BitDepth::Eight))));
+    }
+
+    #[test]
+    fn missing_heightmap_falls_back_to_the_border_or_void() {
+        let settings = test_settings();
+
+        let bordered = GeneratorKind::Heightmap(test_heightmap(Some(20)));
+        assert!(matches!(bordered.build(&settings), Err(HeightmapError::Io(_))));
+
+        let generator = bordered.build_or_fallback(&settings);
+        assert_eq!(column(generator.as_ref(), 5, -3)[19], BLOCK_GRASS);
+        assert_eq!(column(generator.as_ref(), 5, -3)[20], BLOCK_AIR);
+
+        let generator = GeneratorKind::Heightmap(test_heightmap(None)).build_or_fallback(&settings);
+        assert!(column(generator.as_ref(), 5, -3).iter().all(|block| *block == BLOCK_AIR));
+    }
+}
@@ -0,0 +1,126 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use bevy::math::Affine3A;
+use bevy::prelude::*;
+use bevy::render::primitives::{Aabb, Frustum};
+
+// Sections outside of the view count as twice as far away, so the visible part of the world loads first
+// The priorities are squared distances, so the factor is squared as well
+const OUT_OF_VIEW_FACTOR : u64 = 4;
+
+// Position and view of a chunk loader, loaders without a camera have no frustum and see everything
+pub struct LoaderView {
+    pub position: Vec3,
+    pub frustum: Option<Frustum>,
+}
+
+// Section waiting to be generated or meshed
+#[derive(PartialEq, Eq)]
+struct QueuedSection {
+    priority: u64,
+    position: IVec3,
+}
+
+impl Ord for QueuedSection {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Sections with the same priority are sorted by position, so the order never depends on the order they were pushed in
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| self.position.to_array().cmp(&other.position.to_array()))
+    }
+}
+
+impl PartialOrd for QueuedSection {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Sections ordered by how close they are to the chunk loaders, the closest one comes out first
+pub struct LoadQueue<'a> {
+    views: &'a [LoaderView],
+    section_size: Vec3,
+    sections: BinaryHeap<Reverse<QueuedSection>>,
+}
+
+impl<'a> LoadQueue<'a> {
+    pub fn new(views: &'a [LoaderView], section_size: IVec3) -> Self {
+        LoadQueue { views, section_size: section_size.as_vec3(), sections: BinaryHeap::new() }
+    }
+
+    // Add the section with its lowest corner at the world position
+    pub fn push(&mut self, position: IVec3) {
+        let priority : u64 = self.priority(position);
+        self.sections.push(Reverse(QueuedSection { priority, position }));
+    }
+
+    // Take the section with the highest priority
+    pub fn pop(&mut self) -> Option<IVec3> {
+        self.sections.pop().map(|Reverse(section)| section.position)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    // Squared distance from the middle of the section to the closest loader, lower values go first
+    fn priority(&self, position: IVec3) -> u64 {
+        let min : Vec3 = position.as_vec3();
+        let max : Vec3 = min + self.section_size;
+        let bounds = Aabb::from_min_max(min, max);
+
+        self.views
+            .iter()
+            .map(|view| {
+                let distance : u64 = view.position.distance_squared(bounds.center.into()) as u64;
+
+                // The far plane is ignored, chunks behind it are still in front of the camera
+                let in_view : bool = view.frustum.is_none_or(|frustum| frustum.intersects_obb(&bounds, &Affine3A::IDENTITY, true, false));
+
+                if in_view { distance } else { distance * OUT_OF_VIEW_FACTOR }
+            })
+            .min()
+            .unwrap_or(u64::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::camera::CameraProjection;
+    use crate::world::SECTION_HEIGHT;
+
+    #[test]
+    fn load_queue_prefers_close_and_visible_sections() {
+        let size = IVec3::new(16, SECTION_HEIGHT, 16);
+
+        // Camera in the middle of the section at 0, 0, 0, looking north along -z
+        let camera = Transform::from_xyz(8.0, 16.0, 8.0).looking_at(Vec3::new(8.0, 16.0, -100.0), Vec3::Y);
+        let projection = PerspectiveProjection::default().get_projection_matrix();
+        let frustum = Frustum::from_view_projection(&(projection * camera.compute_matrix().inverse()));
+        let views = [LoaderView { position: camera.translation, frustum: Some(frustum) }];
+
+        let mut queue = LoadQueue::new(&views, size);
+        let far_north = IVec3::new(0, 0, -48);
+        let north = IVec3::new(0, 0, -32);
+        let south = IVec3::new(0, 0, 32);
+        let here = IVec3::ZERO;
+
+        for position in [far_north, south, here, north] {
+            queue.push(position);
+        }
+
+        // The section behind the camera is as far away as the one in front, but only the one in front is visible
+        assert_eq!(queue.len(), 4);
+        assert_eq!(queue.pop(), Some(here));
+        assert_eq!(queue.pop(), Some(north));
+        assert_eq!(queue.pop(), Some(far_north));
+        assert_eq!(queue.pop(), Some(south));
+        assert_eq!(queue.pop(), None);
+    }
+}
@@ -0,0 +1,393 @@
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy::utils::HashMap;
+
+use super::atlas::{ATTRIBUTE_TILE, NO_TEXTURE};
+use super::bitmask;
+use super::greedy::{greedy_quads, FaceKey, Quad};
+use super::{find_neighbor_sections, BlockDefinition, BlockId, BlockRegistry, Chunk, Direction, Section, DIRECTIONS, SECTION_HEIGHT};
+
+// The blocks of a chunk that end up in the same mesh
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MeshPass {
+    // Every visible block that isn't transparent, faces are hidden by other opaque blocks
+    Opaque,
+    // Visible transparent blocks like water, faces are only shown next to invisible blocks like air
+    Transparent,
+}
+
+impl MeshPass {
+    // Check if the block type gets meshed in this pass
+    pub fn contains(&self, registry: &BlockRegistry, block_type: BlockId) -> bool {
+        let block = registry.get(block_type);
+
+        match self {
+            MeshPass::Opaque => block.visible && !block.transparent,
+            MeshPass::Transparent => block.visible && block.transparent,
+        }
+    }
+
+    // Check if a face next to the given block type is visible
+    pub fn is_exposed(&self, registry: &BlockRegistry, neighbor_type: BlockId) -> bool {
+        let neighbor = registry.get(neighbor_type);
+
+        match self {
+            MeshPass::Opaque => neighbor.transparent,
+            MeshPass::Transparent => neighbor.transparent && !neighbor.visible,
+        }
+    }
+}
+
+// Which mesher finds the faces of the sections, both build the same mesh
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MesherKind {
+    // Checks the neighbors of every block one by one, works with every chunk width
+    PerVoxel,
+    // Checks whole rows of blocks at once with bit operations, chunks wider than 32 blocks fall back to PerVoxel
+    Bitmask,
+}
+
+// The eight chunks around a chunk, by their offset in chunks
+pub type Neighbors<'a> = HashMap<IVec2, &'a Chunk>;
+
+// The 26 sections around a section, by their offset in sections
+pub(super) type NeighborSections<'a> = HashMap<IVec3, &'a Section>;
+
+// Brightness of a corner for each ambient occlusion level, 0 is a corner between two solid blocks
+const AO_BRIGHTNESS : [f32; 4] = [0.5, 0.7, 0.85, 1.0];
+
+// Vertices and triangles of a chunk mesh, as plain arrays so they can be built and checked without a renderer
+// Every face is a rectangle of 4 vertices and 6 indices
+#[derive(Clone, Default, Debug)]
+pub struct ChunkMeshData {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    // Go from 0 to the number of blocks along each side of a face, so a texture repeats once per block
+    pub uvs: Vec<[f32; 2]>,
+    pub colors: Vec<[f32; 4]>,
+    // Tile of the texture atlas, NO_TEXTURE for blocks which only have a color
+    pub tiles: Vec<u32>,
+    pub indices: Vec<u32>,
+}
+
+impl ChunkMeshData {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    // Add the faces of the other mesh, moved by the offset
+    pub fn append(&mut self, other: ChunkMeshData, offset: Vec3) {
+        let first_vertex : u32 = self.positions.len() as u32;
+
+        self.positions.extend(other.positions.into_iter().map(|position| (Vec3::from(position) + offset).to_array()));
+        self.normals.extend(other.normals);
+        self.uvs.extend(other.uvs);
+        self.colors.extend(other.colors);
+        self.tiles.extend(other.tiles);
+        self.indices.extend(other.indices.into_iter().map(|index| first_vertex + index));
+    }
+}
+
+// Turn the mesh data into a Bevy mesh which can be rendered
+impl From<ChunkMeshData> for Mesh {
+    fn from(data: ChunkMeshData) -> Self {
+        Mesh::new(PrimitiveTopology::TriangleList)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, data.positions)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, data.normals)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, data.uvs)
+            .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, data.colors)
+            .with_inserted_attribute(ATTRIBUTE_TILE, data.tiles)
+            .with_indices(Some(Indices::U32(data.indices)))
+    }
+}
+
+// Corners of the six faces of a block around its center, in the order of DIRECTIONS
+// The corners go around the face so both triangles face outwards
+const FACE_CORNERS : [[Vec3; 4]; 6] = [
+    // X Direction Position
+    [Vec3::new(0.5, -0.5, -0.5), Vec3::new(0.5, 0.5, -0.5), Vec3::new(0.5, 0.5, 0.5), Vec3::new(0.5, -0.5, 0.5)],
+    [Vec3::new(-0.5, 0.5, 0.5), Vec3::new(-0.5, 0.5, -0.5), Vec3::new(-0.5, -0.5, -0.5), Vec3::new(-0.5, -0.5, 0.5)],
+    // Y Direction Position
+    [Vec3::new(0.5, 0.5, -0.5), Vec3::new(-0.5, 0.5, -0.5), Vec3::new(-0.5, 0.5, 0.5), Vec3::new(0.5, 0.5, 0.5)],
+    [Vec3::new(-0.5, -0.5, -0.5), Vec3::new(0.5, -0.5, -0.5), Vec3::new(0.5, -0.5, 0.5), Vec3::new(-0.5, -0.5, 0.5)],
+    // Z Direction Position
+    [Vec3::new(-0.5, -0.5, 0.5), Vec3::new(0.5, -0.5, 0.5), Vec3::new(0.5, 0.5, 0.5), Vec3::new(-0.5, 0.5, 0.5)],
+    [Vec3::new(-0.5, 0.5, -0.5), Vec3::new(0.5, 0.5, -0.5), Vec3::new(0.5, -0.5, -0.5), Vec3::new(-0.5, -0.5, -0.5)],
+];
+
+// Build the mesh of every section of the chunk for the pass, with the positions relative to the chunk
+// The faces on the border of the chunk are only added where the neighbor chunk is loaded
+pub fn mesh_chunk(chunk: &Chunk, neighbors: &Neighbors, registry: &BlockRegistry, pass: MeshPass, mesher: MesherKind) -> ChunkMeshData {
+    let mut data = ChunkMeshData::default();
+
+    for (section_y, section) in &chunk.sections {
+        if !section.blocks.palette().iter().any(|block| pass.contains(registry, *block)) {
+            continue;
+        }
+
+        let neighbor_sections = find_neighbor_sections(chunk, *section_y, neighbors);
+        let section_data = mesh_section(section, &neighbor_sections, registry, chunk.size, pass, mesher);
+
+        data.append(section_data, Vec3::new(0.0, (section_y * SECTION_HEIGHT) as f32, 0.0));
+    }
+
+    data
+}
+
+// Index of the block at the local position in the blocks of a section
+pub(super) fn section_index(position: IVec3, size: IVec3) -> usize {
+    (position.x + position.z * size.x + position.y * size.x * size.z) as usize
+}
+
+// Get the block at the local position, blocks outside of the section come from the sections around it
+// None if that section isn't loaded
+fn block_at(section: &Section, neighbor_sections: &NeighborSections, position: IVec3, size: IVec3) -> Option<BlockId> {
+    let offset : IVec3 = position.div_euclid(size);
+
+    if offset == IVec3::ZERO {
+        return Some(section.blocks.get(section_index(position, size)));
+    }
+
+    neighbor_sections.get(&offset).map(|neighbor| neighbor.blocks.get(section_index(position.rem_euclid(size), size)))
+}
+
+// Get the block type of the face of the block at the local position which looks in the direction, None if the face is hidden
+// The neighbors of the blocks on the border of the section are in the neighbor sections
+pub(super) fn visible_face(
+    section: &Section,
+    neighbor_sections: &NeighborSections,
+    registry: &BlockRegistry,
+    pass: MeshPass,
+    position: IVec3,
+    direction: Direction,
+    size: IVec3,
+) -> Option<BlockId> {
+    let block_type : BlockId = section.blocks.get(section_index(position, size));
+
+    if !pass.contains(registry, block_type) {
+        return None;
+    }
+
+    let covered : bool = is_face_covered(section, neighbor_sections, position, direction, size, |neighbor_type| !pass.is_exposed(registry, neighbor_type));
+
+    (!covered).then_some(block_type)
+}
+
+// Check if the face of the block at the local position which looks in the direction is covered by the block in front of it
+// Shared by both meshers, so they agree on the faces towards sections which aren't there
+pub(super) fn is_face_covered(
+    section: &Section,
+    neighbor_sections: &NeighborSections,
+    position: IVec3,
+    direction: Direction,
+    size: IVec3,
+    covers: impl Fn(BlockId) -> bool,
+) -> bool {
+    match block_at(section, neighbor_sections, position + direction.offset(), size) {
+        Some(neighbor_type) => covers(neighbor_type),
+        // Without a section above the faces are open to the sky, the other neighbors just aren't loaded yet
+        None => direction != Direction::Up,
+    }
+}
+
+// Ambient occlusion of the four corners of the face of the block at the local position, in the order of FACE_CORNERS
+// Each corner is darkened by the two blocks next to it and the one diagonal to it in front of the face, 3 means none of them is solid
+// Two solid blocks next to a corner close it off completely, so the diagonal one doesn't matter then
+pub(super) fn face_ao(section: &Section, neighbor_sections: &NeighborSections, registry: &BlockRegistry, position: IVec3, face: usize, size: IVec3) -> [u8; 4] {
+    let direction : Direction = DIRECTIONS[face];
+    let axis : usize = direction.axis();
+    let u_axis : usize = (axis + 1) % 3;
+    let v_axis : usize = (axis + 2) % 3;
+    let front : IVec3 = position + direction.offset();
+
+    // Blocks of sections which aren't loaded don't darken anything
+    let is_solid = |offset: IVec3| {
+        block_at(section, neighbor_sections, front + offset, size).is_some_and(|block| MeshPass::Opaque.contains(registry, block))
+    };
+
+    FACE_CORNERS[face].map(|corner| {
+        let mut side_u = IVec3::ZERO;
+        side_u[u_axis] = corner[u_axis].signum() as i32;
+
+        let mut side_v = IVec3::ZERO;
+        side_v[v_axis] = corner[v_axis].signum() as i32;
+
+        let (solid_u, solid_v) = (is_solid(side_u), is_solid(side_v));
+
+        if solid_u && solid_v {
+            0
+        } else {
+            3 - solid_u as u8 - solid_v as u8 - is_solid(side_u + side_v) as u8
+        }
+    })
+}
+
+// Color of the vertices of a block before the ambient occlusion
+// Textured blocks get their color from the texture, so only the alpha of their color is used
+pub(super) fn vertex_color(block: &BlockDefinition) -> Vec4 {
+    match block.texture {
+        Some(_) => Vec4::new(1.0, 1.0, 1.0, block.color.w),
+        None => block.color,
+    }
+}
+
+// Darken the color of a corner by its ambient occlusion, the alpha stays the same
+pub(super) fn shade(color: Vec4, ao: u8) -> Vec4 {
+    (color.truncate() * AO_BRIGHTNESS[ao as usize]).extend(color.w)
+}
+
+// Indices of the two triangles of a rectangle of faces with the ambient occlusion of its corners
+// The colors get blended across each triangle, so the rectangle is split along the diagonal with the darker corners,
+// otherwise a single dark corner would only darken one of the triangles and the shading would depend on the orientation of the face
+fn quad_indices(ao: [u8; 4]) -> [u32; 6] {
+    if ao[0] + ao[2] > ao[1] + ao[3] {
+        [1, 2, 3, 3, 0, 1]
+    } else {
+        [0, 1, 2, 2, 3, 0]
+    }
+}
+
+// Build the mesh of a single section, faces of the same block type next to each other get merged into one big rectangle
+// The positions start at 0 at the bottom of the section, the section mesh gets moved to its height
+pub(super) fn mesh_section(
+    section: &Section,
+    neighbor_sections: &NeighborSections,
+    registry: &BlockRegistry,
+    size: IVec3,
+    pass: MeshPass,
+    mesher: MesherKind,
+) -> ChunkMeshData {
+    let mut data = ChunkMeshData::default();
+    let mut add_quad = |face: usize, slice: i32, quad: Quad| push_quad(&mut data, registry, face, slice, quad);
+
+    if mesher == MesherKind::Bitmask && size.max_element() <= bitmask::MAX_BITMASK_WIDTH {
+        bitmask::section_quads(section, neighbor_sections, registry, size, pass, &mut add_quad);
+    } else {
+        voxel_quads(section, neighbor_sections, registry, size, pass, &mut add_quad);
+    }
+
+    data
+}
+
+// Find the merged faces of a section by checking every block on its own
+// Gives every rectangle to add_quad, with the face index of its direction and the slice it lies in
+fn voxel_quads(
+    section: &Section,
+    neighbor_sections: &NeighborSections,
+    registry: &BlockRegistry,
+    size: IVec3,
+    pass: MeshPass,
+    add_quad: &mut impl FnMut(usize, i32, Quad),
+) {
+    // Go through the section slice by slice for every direction, all faces of a slice lie in the same plane
+    for (face, direction) in DIRECTIONS.iter().enumerate() {
+        let axis : usize = direction.axis();
+        let u_axis : usize = (axis + 1) % 3;
+        let v_axis : usize = (axis + 2) % 3;
+
+        let mut mask: Vec<Option<FaceKey>> = vec![None; (size[u_axis] * size[v_axis]) as usize];
+
+        for slice in 0..size[axis] {
+            for v in 0..size[v_axis] {
+                for u in 0..size[u_axis] {
+                    let mut position = IVec3::ZERO;
+                    position[axis] = slice;
+                    position[u_axis] = u;
+                    position[v_axis] = v;
+
+                    mask[(u + v * size[u_axis]) as usize] = visible_face(section, neighbor_sections, registry, pass, position, *direction, size)
+                        .map(|block| FaceKey { block, ao: face_ao(section, neighbor_sections, registry, position, face, size) });
+                }
+            }
+
+            for quad in greedy_quads(&mut mask, size[u_axis], size[v_axis]) {
+                add_quad(face, slice, quad);
+            }
+        }
+    }
+}
+
+// Add the vertices and triangles of a rectangle of faces to the mesh
+fn push_quad(data: &mut ChunkMeshData, registry: &BlockRegistry, face: usize, slice: i32, quad: Quad) {
+    let direction : Direction = DIRECTIONS[face];
+    let axis : usize = direction.axis();
+    let u_axis : usize = (axis + 1) % 3;
+    let v_axis : usize = (axis + 2) % 3;
+
+    let mut min = IVec3::ZERO;
+    min[axis] = slice;
+    min[u_axis] = quad.u;
+    min[v_axis] = quad.v;
+
+    let mut extent = IVec3::ONE;
+    extent[u_axis] = quad.width;
+    extent[v_axis] = quad.height;
+
+    // Stretch the corners of a single face over all blocks of the rectangle
+    let first_vertex : u32 = data.positions.len() as u32;
+
+    let block = registry.get(quad.key.block);
+    let color : Vec4 = vertex_color(block);
+    let tile : u32 = block.texture.map_or(NO_TEXTURE, |texture| texture.tile(direction));
+
+    for (corner, ao) in FACE_CORNERS[face].iter().zip(quad.key.ao) {
+        let stretched : Vec3 = (*corner + 0.5) * extent.as_vec3();
+
+        // The uvs count the blocks across the face, on the sides v goes down so the top of a tile is at the top of the block
+        let uv : Vec2 = match axis {
+            0 => Vec2::new(stretched.z, extent.y as f32 - stretched.y),
+            1 => Vec2::new(stretched.x, stretched.z),
+            _ => Vec2::new(stretched.x, extent.y as f32 - stretched.y),
+        };
+
+        data.positions.push((min.as_vec3() - 0.5 + stretched).to_array());
+        data.uvs.push(uv.to_array());
+        data.colors.push(shade(color, ao).to_array());
+        data.tiles.push(tile);
+    }
+
+    data.normals.extend_from_slice(&[direction.offset().as_vec3().to_array(); 4]);
+    data.indices.extend_from_slice(&quad_indices(quad.key.ao).map(|index| first_vertex + index));
+}
+
+// Normal, block position, corner colors and tile of a single block face
+#[cfg(test)]
+pub(super) type SingleFace = (IVec3, IVec3, [Vec4; 4], u32);
+
+// Lowest and highest corner of a rectangle of the mesh
+#[cfg(test)]
+pub(super) fn quad_bounds(corners: &[[f32; 3]]) -> (Vec3, Vec3) {
+    let min : Vec3 = corners.iter().fold(Vec3::MAX, |min, corner| min.min(Vec3::from(*corner)));
+    let max : Vec3 = corners.iter().fold(Vec3::MIN, |max, corner| max.max(Vec3::from(*corner)));
+
+    (min, max)
+}
+
+// Split the rectangles of a mesh back into single block faces, so tests can check the faces of merged meshes
+#[cfg(test)]
+pub(super) fn single_faces(data: &ChunkMeshData) -> Vec<SingleFace> {
+    let mut faces: Vec<SingleFace> = Vec::new();
+
+    for quad in 0..data.positions.len() / 4 {
+        let normal : Vec3 = Vec3::from(data.normals[quad * 4]);
+        let (min, max) = quad_bounds(&data.positions[quad * 4..quad * 4 + 4]);
+        let colors : [Vec4; 4] = [0, 1, 2, 3].map(|corner| Vec4::from(data.colors[quad * 4 + corner]));
+
+        // The faces lie half a block in front of their blocks, the rectangle covers all blocks between its corners
+        let along_normal = normal.abs().cmpgt(Vec3::ZERO);
+        let block : IVec3 = (min - 0.5 * normal).round().as_ivec3();
+        let first : IVec3 = IVec3::select(along_normal, block, (min + 0.5).round().as_ivec3());
+        let last : IVec3 = IVec3::select(along_normal, block, (max - 0.5).round().as_ivec3());
+
+        for y in first.y..=last.y {
+            for z in first.z..=last.z {
+                for x in first.x..=last.x {
+                    faces.push((normal.as_ivec3(), IVec3::new(x, y, z), colors, data.tiles[quad * 4]));
+                }
+            }
+        }
+    }
+
+    faces
+}
@@ -0,0 +1,371 @@
+use bevy::prelude::*;
+
+use super::biome::{self, BIOMES};
+use super::caves::Caves;
+use super::generator::TerrainGenerator;
+use super::ores::Ores;
+use super::{BiomeMap, BiomeParams, BlockId, NoiseStream, NoiseType, TerrainMode, TerrainNoise, WorldGenSettings};
+use super::{BLOCK_AIR, BLOCK_BEDROCK, BLOCK_SAND, BLOCK_STONE, BLOCK_WATER};
+
+// The default terrain: biomes, rivers, caves and ores, all made out of seeded noise
+pub struct NoiseTerrain {
+    settings: WorldGenSettings,
+    biomes: BiomeMap,
+    height: TerrainNoise,
+    density: TerrainNoise,
+    caves: Caves,
+    ores: Ores,
+    rivers: TerrainNoise,
+    // Every block at and above this height is empty space, whatever the columns look like
+    sky_from: i32,
+    // Every block below this height is stone, ores, bedrock or caves, whatever the columns look like
+    stone_below: i32,
+}
+
+impl NoiseTerrain {
+    pub fn new(settings: &WorldGenSettings) -> Self {
+        let (sky_from, stone_below) = Self::surface_bounds(settings);
+
+        NoiseTerrain {
+            settings: settings.clone(),
+            biomes: BiomeMap::new(settings),
+            height: TerrainNoise::from_settings(settings),
+            density: TerrainNoise::new(settings.seed, NoiseStream::Density, settings.octaves, settings.density_scale, settings.lacunarity, settings.persistence, NoiseType::Standard),
+            caves: Caves::new(settings),
+            ores: Ores::new(settings),
+            rivers: TerrainNoise::new(settings.seed, NoiseStream::Rivers, 2, settings.river_scale, 2.0, 0.5, NoiseType::Standard),
+            sky_from,
+            stone_below,
+        }
+    }
+
+    // Get the heights between which the surface of every column lies, with some room for the blocks right under it
+    // Sections wholly outside of them don't need the height and biome of their columns, which is most of the generation time
+    fn surface_bounds(settings: &WorldGenSettings) -> (i32, i32) {
+        let (min_height, max_height) = biome::height_range(settings.amplitude as f64);
+        let mut min_height : f64 = settings.ground_level as f64 + min_height;
+        let max_height : f64 = settings.ground_level as f64 + max_height;
+
+        // Rivers carve the terrain down to their bed, but never deeper
+        if settings.river_width > 0.0 {
+            min_height = min_height.min((settings.sea_level - settings.river_depth) as f64);
+        }
+
+        // The 3D noise moves the surface up or down by up to the density strength
+        let spread : f64 = match settings.terrain_mode {
+            TerrainMode::Heightmap => 0.0,
+            TerrainMode::Density => settings.density_strength,
+        };
+
+        let max_filler_depth : i32 = BIOMES.iter().map(|biome| biome.params().filler_depth).max().unwrap_or(0);
+
+        let sky_from : i32 = (max_height + spread).ceil() as i32 + 1;
+        let stone_below : i32 = (min_height - spread).floor() as i32 - 2 - max_filler_depth;
+
+        (sky_from, stone_below)
+    }
+
+    // Get the height of the terrain at x, z and the biome which decides the blocks of the column
+    // The height is a blend of the height curves of all biomes, weighted by how close the climate at x, z is to them
+    fn surface_height(&self, x: i32, z: i32) -> (f64, BiomeParams) {
+        let settings = &self.settings;
+        let value : f64 = self.height.get_2d(x as f64, z as f64);
+        let weights = self.biomes.weights_at(x, z);
+
+        let mut height : f64 = settings.ground_level as f64 + biome::blended_height(&weights, value, settings.amplitude as f64);
+        let mut params = biome::dominant_biome(&weights).params();
+
+        // Rivers follow the line where the river noise crosses zero and carve the terrain down to their bed
+        let river : f64 = self.rivers.get_2d(x as f64, z as f64).abs();
+        let river_bed : f64 = (settings.sea_level - settings.river_depth) as f64;
+
+        if river < settings.river_width && river_bed < height {
+            // 1 in the middle of the river and 0 at its banks, smoothed so the banks don't get steep walls
+            let strength : f64 = 1.0 - river / settings.river_width;
+            let strength : f64 = strength * strength * (3.0 - 2.0 * strength);
+
+            height += (river_bed - height) * strength;
+
+            if strength > 0.5 {
+                params.surface_block = BLOCK_SAND;
+            }
+        }
+
+        (height, params)
+    }
+
+    // Get the block of empty space at y, which is water below the sea level
+    fn get_air(&self, y: i32) -> BlockId {
+        if y < self.settings.sea_level {
+            BLOCK_WATER
+        } else {
+            BLOCK_AIR
+        }
+    }
+
+    // Everything at and below the bedrock level is bedrock, without a bedrock level the stone goes down forever
+    fn is_bedrock(&self, y: i32) -> bool {
+        self.settings.bedrock_level.is_some_and(|bedrock_level| y <= bedrock_level)
+    }
+
+    // Get the block of the stone layer at x, y, z, which is either stone or an ore vein
+    fn get_stone(&self, x: i32, y: i32, z: i32) -> BlockId {
+        self.ores.ore_at(x, y, z).unwrap_or(BLOCK_STONE)
+    }
+
+    // Get the block at x, y, z below every possible surface, where the height and biome of the column don't matter
+    fn get_underground_block(&self, x: i32, y: i32, z: i32) -> BlockId {
+        if self.is_bedrock(y) {
+            BLOCK_BEDROCK
+        } else if self.settings.terrain_mode == TerrainMode::Density && self.caves.is_cave(x, y, z) {
+            self.get_air(y)
+        } else {
+            self.get_stone(x, y, z)
+        }
+    }
+
+    // Get the block at y above every possible surface
+    fn get_sky_block(&self, y: i32) -> BlockId {
+        if self.is_bedrock(y) {
+            BLOCK_BEDROCK
+        } else {
+            self.get_air(y)
+        }
+    }
+
+    // Get the value of the given 2D noise at x, z and choose the corresponding block type
+    pub fn get_block(&self, x: i32, y: i32, z: i32) -> BlockId {
+        let (height, biome) = self.surface_height(x, z);
+
+        self.get_column_block(x, y, z, height, &biome)
+    }
+
+    // Get the block at x, y, z of a column with an already sampled surface height and biome
+    // From top to bottom a column has the surface block, some filler blocks, stone with ores and bedrock at the bedrock level
+    fn get_column_block(&self, x: i32, y: i32, z: i32, height: f64, biome: &BiomeParams) -> BlockId {
+        let surface_y : i32 = height as i32;
+
+        if self.is_bedrock(y) {
+            BLOCK_BEDROCK
+        } else if y >= surface_y {
+            self.get_air(y)
+        } else if y == surface_y - 1 {
+            biome.surface_block
+        } else if y >= surface_y - 1 - biome.filler_depth {
+            biome.filler_block
+        } else {
+            self.get_stone(x, y, z)
+        }
+    }
+
+    // Get the density of the terrain at x, y, z, everything above 0 is solid
+    // The height gradient keeps the ground below the surface and the sky above it, the 3D noise adds overhangs and arches
+    fn get_density(&self, x: i32, y: i32, z: i32, height: f64) -> f64 {
+        let gradient : f64 = height - y as f64;
+
+        // The noise can't change anything this far away from the surface, so skip sampling it
+        if gradient.abs() > self.settings.density_strength {
+            return gradient;
+        }
+
+        gradient + self.density.get_3d(x as f64, y as f64, z as f64) * self.settings.density_strength
+    }
+
+    // Get the block type of the 3D density terrain at x, y, z
+    pub fn get_block_density(&self, x: i32, y: i32, z: i32) -> BlockId {
+        let (height, biome) = self.surface_height(x, z);
+
+        self.get_column_block_density(x, y, z, height, &biome)
+    }
+
+    // Get the density terrain block at x, y, z of a column with an already sampled surface height and biome
+    fn get_column_block_density(&self, x: i32, y: i32, z: i32, height: f64, biome: &BiomeParams) -> BlockId {
+        if self.is_bedrock(y) {
+            BLOCK_BEDROCK
+        } else if self.get_density(x, y, z, height) <= 0.0 || self.caves.is_cave(x, y, z) {
+            self.get_air(y)
+        // Blocks with open sky above get the surface block, overhangs included
+        } else if self.get_density(x, y + 1, z, height) <= 0.0 {
+            biome.surface_block
+        } else if y >= height as i32 - 1 - biome.filler_depth {
+            biome.filler_block
+        } else {
+            self.get_stone(x, y, z)
+        }
+    }
+}
+
+impl TerrainGenerator for NoiseTerrain {
+    fn generate(&self, position: IVec3, size: IVec3) -> Vec<BlockId> {
+        let num_voxels: i32 = size.x * size.y * size.z;
+        let mut blocks: Vec<BlockId> = Vec::with_capacity(num_voxels as usize);
+
+        let in_sky : bool = position.y >= self.sky_from;
+        let underground : bool = position.y + size.y <= self.stone_below;
+
+        // The height and biome only depend on x and z, so sample them once per column instead of once per block
+        // Boxes wholly above or below the surface don't need them at all
+        let columns: Vec<(f64, BiomeParams)> = if in_sky || underground {
+            Vec::new()
+        } else {
+            (0..size.x * size.z)
+                .map(|i| self.surface_height(position.x + i % size.x, position.z + i / size.x))
+                .collect()
+        };
+
+        for i in 0..num_voxels {
+            let x: i32 = i % size.x;
+            let z: i32 = (i % (size.x * size.z)) / size.x;
+            let y: i32 = i / (size.x * size.z);
+            let world : IVec3 = position + IVec3::new(x, y, z);
+
+            // Heightmap or 3D density terrain, depending on the settings
+            let block_type : BlockId = if in_sky {
+                self.get_sky_block(world.y)
+            } else if underground {
+                self.get_underground_block(world.x, world.y, world.z)
+            } else {
+                let (height, biome) = &columns[(x + z * size.x) as usize];
+
+                match self.settings.terrain_mode {
+                    TerrainMode::Heightmap => self.get_column_block(world.x, world.y, world.z, *height, biome),
+                    TerrainMode::Density => self.get_column_block_density(world.x, world.y, world.z, *height, biome),
+                }
+            };
+
+            blocks.push(block_type);
+        }
+
+        blocks
+    }
+
+    fn has_decorations(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::mesher::{self, NeighborSections};
+    use crate::world::{BlockRegistry, MeshPass, MesherKind, Section, SECTION_HEIGHT};
+
+    // The sea level is above the ground level, so most columns are under water
+    fn test_settings() -> WorldGenSettings {
+        WorldGenSettings { seed: 4, ground_level: 100, sea_level: 120, ..default() }
+    }
+
+    #[test]
+    fn empty_space_below_the_sea_level_is_water() {
+        let settings = test_settings();
+        let terrain = NoiseTerrain::new(&settings);
+        let mut water : usize = 0;
+
+        for (x, z) in (0..8).flat_map(|x| (0..8).map(move |z| (x * 53, z * 71))) {
+            for y in 0..200 {
+                match terrain.get_block(x, y, z) {
+                    BLOCK_AIR => assert!(y >= settings.sea_level, "{} {} {}", x, y, z),
+                    BLOCK_WATER => {
+                        assert!(y < settings.sea_level, "{} {} {}", x, y, z);
+                        water += 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        assert!(water > 0);
+    }
+
+    #[test]
+    fn rivers_carve_sand_beds_below_the_sea_level() {
+        // Sea level below the ground, so only the rivers are under water
+        let settings = WorldGenSettings { sea_level: 96, ..test_settings() };
+        let terrain = NoiseTerrain::new(&settings);
+        let river_bed : f64 = (settings.sea_level - settings.river_depth) as f64;
+
+        // Height of the column without the river, the rivers only carve the ground above their bed
+        let ground = |x: i32, z: i32| -> f64 {
+            let value : f64 = terrain.height.get_2d(x as f64, z as f64);
+            settings.ground_level as f64 + biome::blended_height(&terrain.biomes.weights_at(x, z), value, settings.amplitude as f64)
+        };
+
+        // Columns right in the middle of a river
+        let rivers: Vec<(i32, i32)> = (0..4000)
+            .map(|x| (x, 0))
+            .filter(|(x, z)| terrain.rivers.get_2d(*x as f64, *z as f64).abs() < settings.river_width * 0.1 && ground(*x, *z) > river_bed)
+            .collect();
+
+        assert!(!rivers.is_empty());
+
+        for (x, z) in rivers {
+            let (height, biome) = terrain.surface_height(x, z);
+            let top : i32 = height as i32 - 1;
+
+            assert!(height < river_bed + 1.0, "{} {} at {}", x, z, height);
+            assert_eq!(biome.surface_block, BLOCK_SAND);
+            assert_eq!(terrain.get_block(x, top, z), BLOCK_SAND);
+            assert!((top + 1..settings.sea_level).all(|y| terrain.get_block(x, y, z) == BLOCK_WATER), "{} {}", x, z);
+        }
+    }
+
+    #[test]
+    fn sections_above_and_below_the_surface_match_the_columns() {
+        let size = IVec3::new(8, SECTION_HEIGHT, 8);
+
+        for terrain_mode in [TerrainMode::Heightmap, TerrainMode::Density] {
+            let settings = WorldGenSettings { terrain_mode, bedrock_level: Some(-40), ..test_settings() };
+            let terrain = NoiseTerrain::new(&settings);
+
+            // Both kinds of sections get skipped, otherwise this test checks nothing
+            assert!(terrain.sky_from <= 1024 && terrain.stone_below >= 32, "{:?}", terrain_mode);
+
+            for y in [-64, 0, 64, 96, 128, 160, 192, 1024] {
+                let position = IVec3::new(-24, y, 40);
+                let blocks = terrain.generate(position, size);
+
+                for (i, block) in blocks.iter().enumerate() {
+                    let i : i32 = i as i32;
+                    let (x, y, z) = (position.x + i % size.x, position.y + i / (size.x * size.z), position.z + (i / size.x) % size.z);
+
+                    let expected = match terrain_mode {
+                        TerrainMode::Heightmap => terrain.get_block(x, y, z),
+                        TerrainMode::Density => terrain.get_block_density(x, y, z),
+                    };
+
+                    assert_eq!(*block, expected, "{:?} {} {} {}", terrain_mode, x, y, z);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn water_is_only_in_the_transparent_mesh() {
+        let settings = test_settings();
+        let size = IVec3::new(16, SECTION_HEIGHT, 16);
+        let registry = BlockRegistry::default();
+
+        // The section with the ground and the surface of the sea
+        let blocks: Vec<BlockId> = NoiseTerrain::new(&settings).generate(IVec3::new(0, 96, 0), size);
+        let section = Section::new(&blocks);
+        let block_at = |position: IVec3| blocks[mesher::section_index(position, size)];
+
+        let opaque = mesher::mesh_section(&section, &NeighborSections::new(), &registry, size, MeshPass::Opaque, MesherKind::Bitmask);
+        let transparent = mesher::mesh_section(&section, &NeighborSections::new(), &registry, size, MeshPass::Transparent, MesherKind::Bitmask);
+
+        assert!(!transparent.is_empty());
+        assert!(mesher::single_faces(&transparent).iter().all(|(_, block, ..)| block_at(*block) == BLOCK_WATER));
+        assert!(mesher::single_faces(&opaque).iter().all(|(_, block, ..)| block_at(*block) != BLOCK_WATER));
+
+        // The ground under the water still gets its top faces
+        let sea_floor: Vec<IVec3> = (0..size.x * size.z)
+            .flat_map(|i| (0..size.y - 1).map(move |y| IVec3::new(i % size.x, y, i / size.x)))
+            .filter(|position| block_at(*position) != BLOCK_WATER && block_at(*position + IVec3::Y) == BLOCK_WATER)
+            .collect();
+
+        assert!(!sea_floor.is_empty());
+
+        let opaque_faces : usize = mesher::single_faces(&opaque).into_iter().filter(|(normal, block, ..)| *normal == IVec3::Y && sea_floor.contains(block)).count();
+        assert_eq!(opaque_faces, sea_floor.len());
+    }
+}
@@ -0,0 +1,141 @@
+use super::{BlockId, NoiseStream, NoiseType, TerrainNoise, WorldGenSettings};
+use super::{BLOCK_COAL_ORE, BLOCK_DIAMOND_ORE, BLOCK_GOLD_ORE, BLOCK_IRON_ORE};
+
+// Entry of the ore table
+// Veins appear where the noise is above the threshold, the threshold sinks with depth so ores get more common further down
+struct OreParams {
+    block_type: BlockId,
+    // Height range of the ore, counted from the bedrock level, so the ores stay above it wherever it is
+    min_y: i32,
+    max_y: i32,
+    // Threshold at max_y and at min_y
+    threshold_top: f64,
+    threshold_bottom: f64,
+    // Frequency of the noise, higher values give smaller veins
+    scale: f64,
+}
+
+const ORES : [OreParams; 4] = [
+    OreParams { block_type: BLOCK_COAL_ORE, min_y: 1, max_y: 128, threshold_top: 0.5, threshold_bottom: 0.4, scale: 0.12 },
+    OreParams { block_type: BLOCK_IRON_ORE, min_y: 1, max_y: 64, threshold_top: 0.55, threshold_bottom: 0.45, scale: 0.15 },
+    OreParams { block_type: BLOCK_GOLD_ORE, min_y: 1, max_y: 32, threshold_top: 0.6, threshold_bottom: 0.5, scale: 0.18 },
+    OreParams { block_type: BLOCK_DIAMOND_ORE, min_y: 1, max_y: 16, threshold_top: 0.65, threshold_bottom: 0.55, scale: 0.2 },
+];
+
+// Ore veins inside the stone layer
+#[derive(Clone)]
+pub struct Ores {
+    noises: Vec<TerrainNoise>,
+    // Height the ore ranges start at, the bedrock level or 0 for a world without bedrock
+    floor: i32,
+}
+
+impl Ores {
+    pub fn new(settings: &WorldGenSettings) -> Self {
+        // Every ore gets its own noise stream, so the veins of different ores don't overlap
+        let noises: Vec<TerrainNoise> = ORES
+            .iter()
+            .enumerate()
+            .map(|(i, ore)| TerrainNoise::new(settings.seed, NoiseStream::Ore(i as u32), 1, ore.scale, 2.0, 0.5, NoiseType::Standard))
+            .collect();
+
+        Ores { noises, floor: settings.bedrock_level.unwrap_or(0) }
+    }
+
+    // Get the ore at x, y, z, if there is one
+    // The rarest ore comes last, so it wins if veins overlap
+    pub fn ore_at(&self, x: i32, y: i32, z: i32) -> Option<BlockId> {
+        let mut found : Option<BlockId> = None;
+        let height : i32 = y - self.floor;
+
+        for (ore, noise) in ORES.iter().zip(&self.noises) {
+            if height < ore.min_y || height > ore.max_y {
+                continue;
+            }
+
+            let depth : f64 = (ore.max_y - height) as f64 / (ore.max_y - ore.min_y).max(1) as f64;
+            let threshold : f64 = ore.threshold_top + (ore.threshold_bottom - ore.threshold_top) * depth;
+
+            if noise.get_3d(x as f64, y as f64, z as f64) > threshold {
+                found = Some(ore.block_type);
+            }
+        }
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::*;
+    use crate::world::{BiomeMap, NoiseTerrain, BLOCK_AIR, BLOCK_BEDROCK, BLOCK_DIRT, BLOCK_GRASS, BLOCK_STONE};
+
+    // No water and no rivers, so every column ends with the blocks of its biome
+    fn test_settings(bedrock_level: Option<i32>) -> WorldGenSettings {
+        WorldGenSettings { sea_level: 1, river_width: 0.0, bedrock_level, ..default() }
+    }
+
+    #[test]
+    fn columns_have_surface_filler_stone_and_bedrock_layers() {
+        let settings = test_settings(Some(0));
+        let terrain = NoiseTerrain::new(&settings);
+        let biomes = BiomeMap::new(&settings);
+        let mut plains : usize = 0;
+
+        for (x, z) in (0..8).flat_map(|x| (0..8).map(move |z| (x * 97, z * 131))) {
+            let biome = biomes.biome_at(x, z).params();
+            let column: Vec<BlockId> = (0..256).map(|y| terrain.get_block(x, y, z)).collect();
+            let top : usize = column.iter().rposition(|block| *block != BLOCK_AIR).unwrap();
+            let filler_depth : usize = biome.filler_depth as usize;
+
+            assert_eq!(column[top], biome.surface_block, "{} {}", x, z);
+            assert!(column[top - filler_depth..top].iter().all(|block| *block == biome.filler_block), "{} {}", x, z);
+            assert_ne!(column[top - filler_depth - 1], biome.filler_block, "{} {}", x, z);
+
+            // Below the filler only stone and ores down to the bedrock, which is only at y 0
+            assert_eq!(column[0], BLOCK_BEDROCK);
+            assert!(column[1..top - filler_depth].iter().all(|block| *block == BLOCK_STONE || ORES.iter().any(|ore| ore.block_type == *block)), "{} {}", x, z);
+
+            if biome.surface_block == BLOCK_GRASS && biome.filler_block == BLOCK_DIRT {
+                plains += 1;
+            }
+        }
+
+        assert!(plains > 0);
+    }
+
+    // The ores found in the stone of a few columns, with their height above the bedrock level
+    fn find_ores(bedrock_level: Option<i32>) -> Vec<(BlockId, i32)> {
+        let ores = Ores::new(&test_settings(bedrock_level));
+        let floor : i32 = bedrock_level.unwrap_or(0);
+        let mut found: Vec<(BlockId, i32)> = Vec::new();
+
+        for x in 0..16 {
+            for z in 0..16 {
+                for y in floor - 32..floor + 160 {
+                    if let Some(ore) = ores.ore_at(x, y, z) {
+                        found.push((ore, y - floor));
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    #[test]
+    fn ores_stay_inside_of_their_height_range_above_the_bedrock() {
+        for bedrock_level in [Some(0), Some(-64), Some(40), None] {
+            let found = find_ores(bedrock_level);
+
+            for ore in &ORES {
+                let heights: Vec<i32> = found.iter().filter(|(block, _)| *block == ore.block_type).map(|(_, height)| *height).collect();
+
+                assert!(!heights.is_empty(), "{:?} {:?}", ore.block_type, bedrock_level);
+                assert!(heights.iter().all(|height| (ore.min_y..=ore.max_y).contains(height)), "{:?} {:?}", ore.block_type, bedrock_level);
+            }
+        }
+    }
+}
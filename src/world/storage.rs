@@ -0,0 +1,188 @@
+use super::BlockId;
+
+// Block storage of a chunk: every block type in the chunk is stored once in the palette,
+// the blocks only store their index into the palette, packed with as few bits as possible
+// A chunk with only 2 block types needs 1 bit per block instead of a whole BlockId
+#[derive(Clone, Debug)]
+pub struct PalettedStorage {
+    len: usize,
+    palette: Vec<BlockId>,
+    // Bits per palette index, 0 while all blocks are the same and the index array isn't needed
+    bits: usize,
+    // The indices never cross the border of a word, so reading one is a single shift and mask
+    data: Vec<u64>,
+}
+
+impl PalettedStorage {
+    // Storage with len blocks of the same type
+    pub fn new(len: usize, block: BlockId) -> Self {
+        PalettedStorage { len, palette: vec![block], bits: 0, data: Vec::new() }
+    }
+
+    pub fn from_blocks(blocks: &[BlockId]) -> Self {
+        let mut palette: Vec<BlockId> = Vec::new();
+
+        // Chunks have few block types, so searching the palette is faster than hashing
+        let indices: Vec<usize> = blocks
+            .iter()
+            .map(|block| match palette.iter().position(|entry| entry == block) {
+                Some(index) => index,
+                None => {
+                    palette.push(*block);
+                    palette.len() - 1
+                }
+            })
+            .collect();
+
+        if palette.len() <= 1 {
+            return PalettedStorage::new(blocks.len(), palette.first().copied().unwrap_or_default());
+        }
+
+        let mut storage = PalettedStorage { len: blocks.len(), palette, bits: 0, data: Vec::new() };
+        storage.repack(indices);
+
+        storage
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // True if all blocks have the same type
+    pub fn is_uniform(&self) -> bool {
+        self.bits == 0
+    }
+
+    // All block types that were ever stored, can include types which were overwritten since
+    pub fn palette(&self) -> &[BlockId] {
+        &self.palette
+    }
+
+    pub fn get(&self, index: usize) -> BlockId {
+        assert!(index < self.len, "block index {} out of range", index);
+
+        if self.bits == 0 {
+            return self.palette[0];
+        }
+
+        self.palette[self.read(index)]
+    }
+
+    pub fn set(&mut self, index: usize, block: BlockId) {
+        assert!(index < self.len, "block index {} out of range", index);
+
+        let palette_index : usize = match self.palette.iter().position(|entry| *entry == block) {
+            Some(palette_index) => palette_index,
+            None => {
+                self.palette.push(block);
+
+                // The palette outgrew the bits of the indices, so repack them with one more bit
+                if self.palette.len() > 1 << self.bits {
+                    let indices: Vec<usize> = (0..self.len).map(|i| self.read(i)).collect();
+                    self.repack(indices);
+                }
+
+                self.palette.len() - 1
+            }
+        };
+
+        // Still only one block type, nothing to write
+        if self.bits == 0 {
+            return;
+        }
+
+        self.write(index, palette_index);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = BlockId> + '_ {
+        (0..self.len).map(|i| self.get(i))
+    }
+
+    // Size of the storage in bytes, including the palette and the index array
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.palette.capacity() * std::mem::size_of::<BlockId>()
+            + self.data.capacity() * std::mem::size_of::<u64>()
+    }
+
+    // Store the palette indices with as many bits as the current palette needs
+    fn repack(&mut self, indices: Vec<usize>) {
+        self.bits = bits_for(self.palette.len());
+
+        let indices_per_word : usize = 64 / self.bits;
+        self.data = vec![0; self.len.div_ceil(indices_per_word)];
+
+        for (i, palette_index) in indices.into_iter().enumerate() {
+            self.write(i, palette_index);
+        }
+    }
+
+    fn read(&self, index: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+
+        let indices_per_word : usize = 64 / self.bits;
+        let shift : usize = (index % indices_per_word) * self.bits;
+        let mask : u64 = (1 << self.bits) - 1;
+
+        ((self.data[index / indices_per_word] >> shift) & mask) as usize
+    }
+
+    fn write(&mut self, index: usize, palette_index: usize) {
+        let indices_per_word : usize = 64 / self.bits;
+        let shift : usize = (index % indices_per_word) * self.bits;
+        let mask : u64 = (1 << self.bits) - 1;
+
+        let word = &mut self.data[index / indices_per_word];
+        *word = (*word & !(mask << shift)) | ((palette_index as u64) << shift);
+    }
+}
+
+// Number of bits to store an index into a palette with the given length
+fn bits_for(palette_len: usize) -> usize {
+    (usize::BITS - (palette_len - 1).leading_zeros()).max(1) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{BLOCK_AIR, BLOCK_COAL_ORE, BLOCK_DIRT, BLOCK_GRASS, BLOCK_LOG, BLOCK_STONE, BLOCK_WATER};
+
+    #[test]
+    fn uniform_storage_has_no_index_array() {
+        let len : usize = 32 * 32 * 256;
+        let storage = PalettedStorage::new(len, BLOCK_AIR);
+
+        assert!(storage.is_uniform());
+        assert_eq!(storage.get(len - 1), BLOCK_AIR);
+        assert!(storage.memory_usage() < 128);
+    }
+
+    #[test]
+    fn storage_grows_palette_on_write() {
+        let len : usize = 32 * 32 * 256;
+        let mut storage = PalettedStorage::new(len, BLOCK_STONE);
+        let blocks : [BlockId; 5] = [BLOCK_DIRT, BLOCK_GRASS, BLOCK_WATER, BLOCK_COAL_ORE, BLOCK_LOG];
+
+        for (i, block) in blocks.iter().enumerate() {
+            storage.set(i * 1000, *block);
+        }
+
+        assert!(!storage.is_uniform());
+        assert_eq!(storage.palette().len(), 6);
+
+        for (i, block) in blocks.iter().enumerate() {
+            assert_eq!(storage.get(i * 1000), *block);
+        }
+        assert_eq!(storage.get(1), BLOCK_STONE);
+        assert_eq!(storage.iter().filter(|block| *block == BLOCK_STONE).count(), len - blocks.len());
+
+        // 6 block types need 3 bits per block, so the storage stays below 4 bits per block
+        assert!(storage.memory_usage() * 8 < len * 4);
+    }
+}
@@ -0,0 +1,172 @@
+use noise::{NoiseFn, Perlin};
+
+use super::WorldGenSettings;
+
+// Shape of every octave before it gets added to the fractal sum
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoiseType {
+    // Plain Perlin noise, rolling hills
+    Standard,
+    // Inverted absolute value, sharp crests like mountain chains
+    Ridged,
+    // Absolute value, round bumps and puffy hills
+    Billow,
+}
+
+// The noises of a world, every one of them gets its own seeds
+// The seeds are hashed from the world seed, the noise and the octave, so different worlds don't share any noise
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NoiseStream {
+    Height,
+    Density,
+    Temperature,
+    Humidity,
+    Rivers,
+    Cheese,
+    // One per noise of the spaghetti caves and per ore
+    Spaghetti(u32),
+    Ore(u32),
+}
+
+impl NoiseStream {
+    // Different for every noise, the index of the spaghetti and ore noises included
+    fn id(&self) -> u64 {
+        let (kind, index) : (u64, u32) = match self {
+            NoiseStream::Height => (0, 0),
+            NoiseStream::Density => (1, 0),
+            NoiseStream::Temperature => (2, 0),
+            NoiseStream::Humidity => (3, 0),
+            NoiseStream::Rivers => (4, 0),
+            NoiseStream::Cheese => (5, 0),
+            NoiseStream::Spaghetti(index) => (6, *index),
+            NoiseStream::Ore(index) => (7, *index),
+        };
+
+        kind << 32 | index as u64
+    }
+}
+
+// One step of splitmix64, a tiny hash which turns close inputs into unrelated outputs
+fn splitmix64(value: u64) -> u64 {
+    let value : u64 = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let value : u64 = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    let value : u64 = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+    value ^ (value >> 31)
+}
+
+// Seed of one octave of one noise of the world
+// Adding offsets to the world seed instead would give the world with seed 1 the noises of the world with seed 0
+pub fn noise_seed(world_seed: u32, stream: NoiseStream, octave: u32) -> u32 {
+    let hash : u64 = splitmix64(splitmix64(splitmix64(world_seed as u64) ^ stream.id()) ^ octave as u64);
+
+    (hash >> 32) as u32
+}
+
+// Fractal noise made out of several Perlin octaves
+// Every octave gets a higher frequency (lacunarity) and a lower amplitude (persistence) than the one before,
+// so the first octave shapes the landscape and the following ones only add smaller details
+#[derive(Clone)]
+pub struct TerrainNoise {
+    octaves: Vec<Perlin>,
+    scale: f64,
+    lacunarity: f64,
+    persistence: f64,
+    noise_type: NoiseType,
+}
+
+impl TerrainNoise {
+    pub fn new(seed: u32, stream: NoiseStream, octaves: usize, scale: f64, lacunarity: f64, persistence: f64, noise_type: NoiseType) -> Self {
+        // Every octave gets its own seed, otherwise all octaves would have the same pattern
+        let octaves: Vec<Perlin> = (0..octaves)
+            .map(|i| Perlin::new(noise_seed(seed, stream, i as u32)))
+            .collect();
+
+        TerrainNoise { octaves, scale, lacunarity, persistence, noise_type }
+    }
+
+    // Terrain height noise of the world described by the settings
+    pub fn from_settings(settings: &WorldGenSettings) -> Self {
+        TerrainNoise::new(
+            settings.seed,
+            NoiseStream::Height,
+            settings.octaves,
+            settings.scale,
+            settings.lacunarity,
+            settings.persistence,
+            settings.noise_type,
+        )
+    }
+
+    // Sample the noise at x, z, the result is in the range -1..1
+    pub fn get_2d(&self, x: f64, z: f64) -> f64 {
+        self.fractal(|perlin, frequency| perlin.get([x * frequency, z * frequency]))
+    }
+
+    // Sample the noise at x, y, z, the result is in the range -1..1
+    pub fn get_3d(&self, x: f64, y: f64, z: f64) -> f64 {
+        self.fractal(|perlin, frequency| perlin.get([x * frequency, y * frequency, z * frequency]))
+    }
+
+    fn fractal(&self, sample: impl Fn(&Perlin, f64) -> f64) -> f64 {
+        let mut value : f64 = 0.0;
+        let mut frequency : f64 = self.scale;
+        let mut amplitude : f64 = 1.0;
+        let mut max_value : f64 = 0.0;
+
+        for perlin in &self.octaves {
+            let noise : f64 = sample(perlin, frequency).clamp(-1.0, 1.0);
+
+            let shaped : f64 = match self.noise_type {
+                NoiseType::Standard => noise,
+                NoiseType::Ridged => 1.0 - 2.0 * noise.abs(),
+                NoiseType::Billow => 2.0 * noise.abs() - 1.0,
+            };
+
+            value += shaped * amplitude;
+            max_value += amplitude;
+
+            frequency *= self.lacunarity;
+            amplitude *= self.persistence;
+        }
+
+        // Normalize, so the amplitude of the terrain doesn't depend on the number of octaves
+        if max_value > 0.0 {
+            value / max_value
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn different_worlds_share_no_noise() {
+        let streams : [NoiseStream; 10] = [
+            NoiseStream::Height,
+            NoiseStream::Density,
+            NoiseStream::Temperature,
+            NoiseStream::Humidity,
+            NoiseStream::Rivers,
+            NoiseStream::Cheese,
+            NoiseStream::Spaghetti(0),
+            NoiseStream::Spaghetti(1),
+            NoiseStream::Ore(0),
+            NoiseStream::Ore(1),
+        ];
+
+        // World seeds which used to share noises because of the offsets, like 1 and 1001
+        let world_seeds: HashSet<u32> = (0..8).flat_map(|seed| [0, 1, 100, 1000, 7000].map(|offset| seed + offset)).collect();
+        let seeds: Vec<u32> = world_seeds
+            .into_iter()
+            .flat_map(|world_seed| streams.iter().flat_map(move |stream| (0..4).map(move |octave| noise_seed(world_seed, *stream, octave))))
+            .collect();
+
+        assert_eq!(seeds.iter().collect::<HashSet<_>>().len(), seeds.len());
+    }
+}